@@ -1,6 +1,19 @@
+// bevy systems take their resources and queries as arguments, and bevy 0.5's derive(Bundle)
+// forgets its fields with mem::forget
+#![allow(
+    clippy::too_many_arguments,
+    clippy::type_complexity,
+    clippy::forget_non_drop
+)]
+
+extern crate anyhow;
 extern crate bevy;
 extern crate fstrings;
 extern crate rand;
+extern crate rand_chacha;
+extern crate ron;
+extern crate serde;
+extern crate serde_json;
 
 // mod chapter1;
 // mod chapter2;
@@ -0,0 +1,637 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use bevy::input::gamepad::*;
+use bevy::input::*;
+use bevy::prelude::*;
+use bevy::window::*;
+
+use crate::test_bevy::*;
+
+pub struct ActionsPlugin;
+
+impl Plugin for ActionsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_startup_system(input_load_bindings.system())
+            .insert_resource(ActionState::default())
+            .insert_resource(RebindState::default())
+            .add_system(input_gamepads.system().label("input_gamepads_system"))
+            .add_system(
+                input_rebind
+                    .system()
+                    .label("input_rebind_system")
+                    .after("input_gamepads_system"),
+            )
+            .add_system(
+                input_actions
+                    .system()
+                    .label("input_actions_system")
+                    .after("input_rebind_system")
+                    .after("cursor_position_system")
+                    .before("player_movement_system"),
+            );
+    }
+}
+
+pub const INPUT_BINDINGS_PATH: &str = "assets/config/input.ron";
+
+// assets/config/input.ron for the first player, input_p2.ron, input_p3.ron... for the others
+pub fn input_bindings_path(player: usize) -> String {
+    if player == 0 {
+        STR(INPUT_BINDINGS_PATH)
+    } else {
+        INPUT_BINDINGS_PATH.replace(".ron", &format!("_p{}.ron", player + 1))
+    }
+}
+
+// What the game reacts to, independent of the key / button / stick doing it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum InputAction {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Fire,
+    Dash,
+    Interact,
+    // walk to the clicked spot, with the mouse control scheme
+    MoveTo,
+    // between keyboard and mouse movement
+    SwitchControls,
+    // in and out of the pause screen, back to the menu from the game over screen
+    Pause,
+    // picks the default choice of the menu, pause and game over screens
+    Confirm,
+}
+
+impl InputAction {
+    pub const ALL: [InputAction; 11] = [
+        InputAction::MoveUp,
+        InputAction::MoveDown,
+        InputAction::MoveLeft,
+        InputAction::MoveRight,
+        InputAction::Fire,
+        InputAction::Dash,
+        InputAction::Interact,
+        InputAction::MoveTo,
+        InputAction::SwitchControls,
+        InputAction::Pause,
+        InputAction::Confirm,
+    ];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum InputBinding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    GamepadButton(GamepadButtonType),
+    // one direction of a stick or trigger, e.g. MoveUp on the positive side of LeftStickY
+    GamepadAxis {
+        axis: GamepadAxisType,
+        positive: bool,
+    },
+}
+
+impl InputBinding {
+    // keyboard and mouse on one side, gamepads on the other
+    pub fn is_gamepad(&self) -> bool {
+        matches!(
+            self,
+            InputBinding::GamepadButton(_) | InputBinding::GamepadAxis { .. }
+        )
+    }
+}
+
+// The bindings as written in assets/config/input.ron. For example:
+//
+// (
+//     bindings: {
+//         MoveUp: [Key(W), Key(Up), GamepadAxis(axis: LeftStickY, positive: true)],
+//         Fire: [Mouse(Left), GamepadButton(RightTrigger2)],
+//     },
+//     gamepad_deadzone: 0.2,
+// )
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputBindings {
+    pub bindings: BTreeMap<InputAction, Vec<InputBinding>>,
+    // stick values below this count as released
+    pub gamepad_deadzone: f32,
+}
+
+impl Default for InputBindings {
+    fn default() -> Self {
+        let gamepad_axis = |axis, positive| InputBinding::GamepadAxis { axis, positive };
+        let bindings = vec![
+            (
+                InputAction::MoveUp,
+                vec![
+                    InputBinding::Key(KeyCode::W),
+                    gamepad_axis(GamepadAxisType::LeftStickY, true),
+                    InputBinding::GamepadButton(GamepadButtonType::DPadUp),
+                ],
+            ),
+            (
+                InputAction::MoveDown,
+                vec![
+                    InputBinding::Key(KeyCode::S),
+                    gamepad_axis(GamepadAxisType::LeftStickY, false),
+                    InputBinding::GamepadButton(GamepadButtonType::DPadDown),
+                ],
+            ),
+            (
+                InputAction::MoveLeft,
+                vec![
+                    InputBinding::Key(KeyCode::A),
+                    gamepad_axis(GamepadAxisType::LeftStickX, false),
+                    InputBinding::GamepadButton(GamepadButtonType::DPadLeft),
+                ],
+            ),
+            (
+                InputAction::MoveRight,
+                vec![
+                    InputBinding::Key(KeyCode::D),
+                    gamepad_axis(GamepadAxisType::LeftStickX, true),
+                    InputBinding::GamepadButton(GamepadButtonType::DPadRight),
+                ],
+            ),
+            (
+                InputAction::Fire,
+                vec![
+                    InputBinding::Mouse(MouseButton::Left),
+                    InputBinding::GamepadButton(GamepadButtonType::RightTrigger2),
+                ],
+            ),
+            (
+                InputAction::Dash,
+                vec![
+                    InputBinding::Key(KeyCode::Space),
+                    InputBinding::GamepadButton(GamepadButtonType::South),
+                ],
+            ),
+            (
+                InputAction::Interact,
+                vec![
+                    InputBinding::Key(KeyCode::E),
+                    InputBinding::GamepadButton(GamepadButtonType::West),
+                ],
+            ),
+            (
+                InputAction::MoveTo,
+                vec![InputBinding::Mouse(MouseButton::Right)],
+            ),
+            (
+                InputAction::SwitchControls,
+                vec![
+                    InputBinding::Key(KeyCode::Tab),
+                    InputBinding::GamepadButton(GamepadButtonType::Select),
+                ],
+            ),
+            (
+                InputAction::Pause,
+                vec![
+                    InputBinding::Key(KeyCode::Escape),
+                    InputBinding::GamepadButton(GamepadButtonType::Start),
+                ],
+            ),
+            (
+                InputAction::Confirm,
+                vec![
+                    InputBinding::Key(KeyCode::Return),
+                    InputBinding::GamepadButton(GamepadButtonType::South),
+                ],
+            ),
+        ];
+        Self {
+            bindings: bindings.into_iter().collect(),
+            gamepad_deadzone: 0.2,
+        }
+    }
+}
+
+impl InputBindings {
+    // The keyboard is shared: the first player gets WASD and the mouse, the second the arrow keys
+    // and the keys around them, the others only their gamepad
+    pub fn default_for(player: usize) -> Self {
+        let mut defaults = Self::default();
+        if player == 0 {
+            return defaults;
+        }
+        for bindings in defaults.bindings.values_mut() {
+            bindings.retain(|binding| binding.is_gamepad());
+        }
+        if player == 1 {
+            let keys = [
+                (InputAction::MoveUp, KeyCode::Up),
+                (InputAction::MoveDown, KeyCode::Down),
+                (InputAction::MoveLeft, KeyCode::Left),
+                (InputAction::MoveRight, KeyCode::Right),
+                (InputAction::Fire, KeyCode::RControl),
+                (InputAction::Dash, KeyCode::RShift),
+                (InputAction::Interact, KeyCode::Return),
+            ];
+            for (action, key) in keys.iter() {
+                defaults.bind(*action, InputBinding::Key(*key));
+            }
+        }
+        defaults
+    }
+
+    // the defaults when the file is missing or broken, so a typo never locks anyone out
+    pub fn load(path: &str, player: usize) -> Self {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(_) => return Self::default_for(player),
+        };
+        match ron::de::from_str(&text) {
+            Ok(bindings) => bindings,
+            Err(err) => {
+                println!("Can't read the input bindings from {}: {}", path, err);
+                Self::default_for(player)
+            }
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), anyhow::Error> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::new())?;
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    pub fn get(&self, action: InputAction) -> &[InputBinding] {
+        self.bindings.get(&action).map_or(&[], |bindings| bindings)
+    }
+
+    pub fn bind(&mut self, action: InputAction, binding: InputBinding) {
+        let bindings = self.bindings.entry(action).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    // The binding only belongs to this action afterwards. It replaces the action's bindings of the
+    // same device class, so rebinding a key leaves the gamepad bindings alone and vice versa.
+    pub fn rebind(&mut self, action: InputAction, binding: InputBinding) {
+        for bindings in self.bindings.values_mut() {
+            bindings.retain(|other| *other != binding);
+        }
+        let bindings = self.bindings.entry(action).or_default();
+        bindings.retain(|other| other.is_gamepad() != binding.is_gamepad());
+        bindings.push(binding);
+    }
+
+    pub fn unbind(&mut self, action: InputAction) {
+        self.bindings.remove(&action);
+    }
+}
+
+// One binding set per local player, indexed by PlayerIdCmp
+pub struct PlayerBindings(pub Vec<InputBindings>);
+
+// The actions of one player in this frame, recorded as they are into a replay
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct PlayerActions {
+    // 0..1, analog for the sticks, 0 or 1 for everything else
+    values: HashMap<InputAction, f32>,
+    just_pressed: HashSet<InputAction>,
+}
+
+impl PlayerActions {
+    pub fn value(&self, action: InputAction) -> f32 {
+        self.values.get(&action).copied().unwrap_or(0.)
+    }
+
+    pub fn pressed(&self, action: InputAction) -> bool {
+        self.value(action) > 0.
+    }
+
+    pub fn just_pressed(&self, action: InputAction) -> bool {
+        self.just_pressed.contains(&action)
+    }
+}
+
+// The actions of this frame, filled by input_actions from the bindings
+#[derive(Default)]
+pub struct ActionState {
+    players: Vec<PlayerActions>,
+    // connected gamepads in the order they came in, the n-th one belongs to the n-th player
+    gamepads: Vec<Gamepad>,
+}
+
+impl ActionState {
+    pub fn player(&self, player: usize) -> Option<&PlayerActions> {
+        self.players.get(player)
+    }
+
+    pub fn players(&self) -> &[PlayerActions] {
+        &self.players
+    }
+
+    pub fn gamepad(&self, player: usize) -> Option<Gamepad> {
+        self.gamepads.get(player).copied()
+    }
+}
+
+pub struct InputDevices<'a> {
+    pub keyboard: &'a Input<KeyCode>,
+    pub mouse: &'a Input<MouseButton>,
+    pub gamepad_buttons: &'a Input<GamepadButton>,
+    pub gamepad_axes: &'a Axis<GamepadAxis>,
+}
+
+// (value, just pressed) of one binding
+pub fn binding_value(
+    binding: &InputBinding,
+    devices: &InputDevices,
+    gamepads: &[Gamepad],
+    deadzone: f32,
+) -> (f32, bool) {
+    let button = |pressed: bool, just_pressed: bool| (pressed as u32 as f32, just_pressed);
+    match *binding {
+        InputBinding::Key(key) => button(
+            devices.keyboard.pressed(key),
+            devices.keyboard.just_pressed(key),
+        ),
+        InputBinding::Mouse(mouse_button) => button(
+            devices.mouse.pressed(mouse_button),
+            devices.mouse.just_pressed(mouse_button),
+        ),
+        InputBinding::GamepadButton(button_type) => {
+            gamepads.iter().fold((0., false), |acc, pad| {
+                let gamepad_button = GamepadButton(*pad, button_type);
+                let (value, just_pressed) = button(
+                    devices.gamepad_buttons.pressed(gamepad_button),
+                    devices.gamepad_buttons.just_pressed(gamepad_button),
+                );
+                (acc.0.max(value), acc.1 || just_pressed)
+            })
+        }
+        // sticks have no just_pressed, the action picks it up from the value crossing 0
+        InputBinding::GamepadAxis { axis, positive } => {
+            let value = gamepads.iter().fold(0_f32, |acc, pad| {
+                let raw = devices
+                    .gamepad_axes
+                    .get(GamepadAxis(*pad, axis))
+                    .unwrap_or(0.);
+                let value = if positive { raw } else { -raw };
+                acc.max(value)
+            });
+            if value < deadzone {
+                (0., false)
+            } else {
+                (value.min(1.), false)
+            }
+        }
+    }
+}
+
+fn input_gamepads(
+    mut action_state: ResMut<ActionState>,
+    mut gamepad_events: EventReader<GamepadEvent>,
+) {
+    for GamepadEvent(gamepad, event_type) in gamepad_events.iter() {
+        match event_type {
+            GamepadEventType::Connected => {
+                println!("Gamepad {} connected", gamepad.0);
+                action_state.gamepads.push(*gamepad);
+            }
+            GamepadEventType::Disconnected => {
+                println!("Gamepad {} disconnected", gamepad.0);
+                action_state.gamepads.retain(|pad| pad != gamepad);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn input_load_bindings(mut commands: Commands, players: Res<LocalPlayers>) {
+    let bindings = (0..players.count)
+        .map(|player| InputBindings::load(&input_bindings_path(player), player))
+        .collect();
+    commands.insert_resource(PlayerBindings(bindings));
+}
+
+fn input_actions(
+    keyboard_input: Res<Input<KeyCode>>,
+    mouse_input: Res<Input<MouseButton>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    bindings: Res<PlayerBindings>,
+    rebind: Res<RebindState>,
+    playback: Option<Res<ReplayPlayback>>,
+    mut action_state: ResMut<ActionState>,
+) {
+    // a replay plays back the recorded actions instead of the devices
+    if let Some(playback) = playback {
+        action_state.players = playback
+            .frame()
+            .map_or_else(Vec::new, |frame| frame.players.clone());
+        return;
+    }
+    let devices = InputDevices {
+        keyboard: &keyboard_input,
+        mouse: &mouse_input,
+        gamepad_buttons: &gamepad_buttons,
+        gamepad_axes: &gamepad_axes,
+    };
+    let action_state = &mut *action_state;
+    action_state
+        .players
+        .resize_with(bindings.0.len(), PlayerActions::default);
+    for (player, player_bindings) in bindings.0.iter().enumerate() {
+        // only its own gamepad, the keyboard is told apart by the bindings
+        let gamepads: Vec<Gamepad> = action_state.gamepad(player).into_iter().collect();
+        let actions = &mut action_state.players[player];
+        actions.just_pressed.clear();
+        for action in InputAction::ALL.iter() {
+            // the input being captured for a rebind shouldn't also do something in the game, nor
+            // should the one ending it (Escape would pause)
+            let (value, just_pressed) = if rebind.action.is_some() || rebind.is_changed() {
+                (0., false)
+            } else {
+                player_bindings
+                    .get(*action)
+                    .iter()
+                    .map(|binding| {
+                        binding_value(
+                            binding,
+                            &devices,
+                            &gamepads,
+                            player_bindings.gamepad_deadzone,
+                        )
+                    })
+                    .fold((0_f32, false), |acc, (value, just_pressed)| {
+                        (acc.0.max(value), acc.1 || just_pressed)
+                    })
+            };
+            let was_pressed = actions.value(*action) > 0.;
+            if just_pressed || (value > 0. && !was_pressed) {
+                actions.just_pressed.insert(*action);
+            }
+            actions.values.insert(*action, value);
+        }
+    }
+}
+
+// F3 walks through all actions, the next key, mouse button, gamepad button or stick direction
+// becomes the only binding of the action. Escape stops early, the bindings are saved either way.
+// Shift+F3 does the same for the second player.
+#[derive(Default)]
+pub struct RebindState {
+    pub player: usize,
+    pub action: Option<InputAction>,
+}
+
+fn input_rebind(
+    keyboard_input: Res<Input<KeyCode>>,
+    mouse_input: Res<Input<MouseButton>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    action_state: Res<ActionState>,
+    mut bindings: ResMut<PlayerBindings>,
+    mut rebind: ResMut<RebindState>,
+    // a stick has to come back to the center before it can be captured again
+    mut stick_held: Local<bool>,
+) {
+    let action = match rebind.action {
+        Some(action) => action,
+        None => {
+            let shift =
+                keyboard_input.pressed(KeyCode::LShift) || keyboard_input.pressed(KeyCode::RShift);
+            let player = if shift { 1 } else { 0 };
+            if keyboard_input.just_pressed(KeyCode::F3) && player < bindings.0.len() {
+                rebind.player = player;
+                rebind.action = Some(InputAction::ALL[0]);
+                println!(
+                    "Player {}: press the input for {:?} (Escape to stop)",
+                    player + 1,
+                    InputAction::ALL[0]
+                );
+            }
+            return;
+        }
+    };
+
+    let captured = if keyboard_input.just_pressed(KeyCode::Escape) {
+        None
+    } else if let Some(key) = keyboard_input
+        .get_just_pressed()
+        .find(|key| **key != KeyCode::F3)
+    {
+        Some(InputBinding::Key(*key))
+    } else if let Some(mouse_button) = mouse_input.get_just_pressed().next() {
+        Some(InputBinding::Mouse(*mouse_button))
+    } else if let Some(GamepadButton(_, button_type)) = gamepad_buttons.get_just_pressed().next() {
+        Some(InputBinding::GamepadButton(*button_type))
+    } else {
+        let stick_axes = [
+            GamepadAxisType::LeftStickX,
+            GamepadAxisType::LeftStickY,
+            GamepadAxisType::RightStickX,
+            GamepadAxisType::RightStickY,
+        ];
+        let stick = action_state.gamepads.iter().find_map(|pad| {
+            stick_axes.iter().find_map(|axis| {
+                let value = gamepad_axes.get(GamepadAxis(*pad, *axis)).unwrap_or(0.);
+                if value.abs() > 0.8 {
+                    Some(InputBinding::GamepadAxis {
+                        axis: *axis,
+                        positive: value > 0.,
+                    })
+                } else {
+                    None
+                }
+            })
+        });
+        let was_held = *stick_held;
+        *stick_held = stick.is_some();
+        stick.filter(|_| !was_held)
+    };
+    let stopped = keyboard_input.just_pressed(KeyCode::Escape);
+    if captured.is_none() && !stopped {
+        return;
+    }
+
+    let player = rebind.player;
+    if let Some(binding) = captured {
+        bindings.0[player].rebind(action, binding);
+        println!(
+            "Player {}: {:?} is now bound to {:?}",
+            player + 1,
+            action,
+            binding
+        );
+    }
+    let next = InputAction::ALL
+        .iter()
+        .position(|other| *other == action)
+        .and_then(|idx| InputAction::ALL.get(idx + 1));
+    match next {
+        Some(next) if !stopped => {
+            rebind.action = Some(*next);
+            println!(
+                "Player {}: press the input for {:?} (Escape to stop)",
+                player + 1,
+                next
+            );
+        }
+        _ => {
+            rebind.action = None;
+            let path = input_bindings_path(player);
+            match bindings.0[player].save(&path) {
+                Ok(()) => println!("Saved the input bindings to {}", path),
+                Err(err) => println!("Can't save the input bindings: {}", err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebind_a_key_keeps_the_gamepad_bindings() {
+        let mut bindings = InputBindings::default();
+        bindings.rebind(InputAction::Dash, InputBinding::Key(KeyCode::LShift));
+        assert_eq!(
+            bindings.get(InputAction::Dash),
+            &[
+                InputBinding::GamepadButton(GamepadButtonType::South),
+                InputBinding::Key(KeyCode::LShift),
+            ]
+        );
+    }
+
+    #[test]
+    fn rebind_a_gamepad_button_keeps_the_keyboard_and_mouse_bindings() {
+        let mut bindings = InputBindings::default();
+        bindings.rebind(
+            InputAction::Fire,
+            InputBinding::GamepadButton(GamepadButtonType::RightTrigger),
+        );
+        assert_eq!(
+            bindings.get(InputAction::Fire),
+            &[
+                InputBinding::Mouse(MouseButton::Left),
+                InputBinding::GamepadButton(GamepadButtonType::RightTrigger),
+            ]
+        );
+    }
+
+    #[test]
+    fn rebind_takes_the_binding_from_the_other_actions() {
+        let mut bindings = InputBindings::default();
+        bindings.rebind(InputAction::Interact, InputBinding::Key(KeyCode::Space));
+        assert_eq!(
+            bindings.get(InputAction::Dash),
+            &[InputBinding::GamepadButton(GamepadButtonType::South)]
+        );
+        assert_eq!(
+            bindings.get(InputAction::Interact),
+            &[
+                InputBinding::GamepadButton(GamepadButtonType::West),
+                InputBinding::Key(KeyCode::Space),
+            ]
+        );
+    }
+}
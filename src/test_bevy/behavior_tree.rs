@@ -0,0 +1,389 @@
+use serde::Deserialize;
+
+use bevy::asset::{AssetLoader, LoadContext, LoadedAsset};
+use bevy::input::*;
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::utils::BoxedFuture;
+use bevy::window::*;
+
+use crate::test_bevy::*;
+
+pub struct BehaviorTreePlugin;
+
+impl Plugin for BehaviorTreePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_asset::<BehaviorTreeAsset>()
+            .init_asset_loader::<BehaviorTreeLoader>()
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .with_system(monster_attach_behavior_tree.system())
+                    .with_system(
+                        monster_behavior_tree
+                            .system()
+                            .label("monster_behavior_tree_system")
+                            .before("monster_steering_system")
+                            .before("monster_collision_system"),
+                    ),
+            );
+    }
+}
+
+// A behavior tree as written in the asset files (*.bt.ron or *.bt.json). For example in RON:
+//
+// Selector([
+//     Sequence([Condition(HealthBelow(0.3)), Action(FleePlayer)]),
+//     Sequence([Condition(PlayerVisible), Condition(PlayerWithin(250.)), Action(ChasePlayer)]),
+//     Action(Wander),
+// ])
+//
+// The tree is stateless: it is evaluated from the root every frame and the first leaf that
+// returns Running decides what the monster does this frame.
+#[derive(Debug, Clone, Deserialize)]
+pub enum BtNode {
+    // runs the children in order until one does not succeed
+    Sequence(Vec<BtNode>),
+    // runs the children in order until one does not fail
+    Selector(Vec<BtNode>),
+    // swaps Success and Failure of the child
+    Invert(Box<BtNode>),
+    // turns a Failure of the child into a Success
+    Succeed(Box<BtNode>),
+    Condition(BtCondition),
+    Action(BtAction),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub enum BtCondition {
+    PlayerVisible,
+    PlayerWithin(f32),
+    // health ratio (current / max)
+    HealthBelow(f32),
+    AwayFromHome(f32),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub enum BtAction {
+    ChasePlayer,
+    FleePlayer,
+    // succeeds once the monster is within the given distance of its home
+    ReturnHome(f32),
+    Wander,
+    Hold,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BtStatus {
+    Success,
+    Failure,
+    Running,
+}
+
+#[derive(Debug, TypeUuid)]
+#[uuid = "6f3c1a0e-5b7d-4c39-9a51-2f8e0d4b7c16"]
+pub struct BehaviorTreeAsset {
+    pub root: BtNode,
+}
+
+#[derive(Default)]
+pub struct BehaviorTreeLoader;
+
+impl AssetLoader for BehaviorTreeLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let is_json = load_context
+                .path()
+                .to_str()
+                .is_some_and(|path| path.ends_with(".json"));
+            let root: BtNode = if is_json {
+                serde_json::from_slice(bytes)?
+            } else {
+                ron::de::from_bytes(bytes)?
+            };
+            load_context.set_default_asset(LoadedAsset::new(BehaviorTreeAsset { root }));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["bt.ron", "bt.json"]
+    }
+}
+
+pub struct BehaviorTreeCmp {
+    pub tree: Handle<BehaviorTreeAsset>,
+    // top-left corner the monster spawned at
+    pub home: Vec2,
+}
+
+// What the leaves get to look at and act on for one monster
+pub struct BtContext<'a> {
+    pub senses: &'a MonsterAiSenses,
+    pub player_center: Option<Vec2>,
+    pub home_center: Vec2,
+    // set by the action leaf that ends up running
+    pub intent: Option<MonsterMoveIntent>,
+}
+
+pub fn tick_behavior_tree(node: &BtNode, ctx: &mut BtContext) -> BtStatus {
+    match node {
+        BtNode::Sequence(children) => {
+            for child in children {
+                let status = tick_behavior_tree(child, ctx);
+                if status != BtStatus::Success {
+                    return status;
+                }
+            }
+            BtStatus::Success
+        }
+        BtNode::Selector(children) => {
+            for child in children {
+                let status = tick_behavior_tree(child, ctx);
+                if status != BtStatus::Failure {
+                    return status;
+                }
+            }
+            BtStatus::Failure
+        }
+        BtNode::Invert(child) => match tick_behavior_tree(child, ctx) {
+            BtStatus::Success => BtStatus::Failure,
+            BtStatus::Failure => BtStatus::Success,
+            BtStatus::Running => BtStatus::Running,
+        },
+        BtNode::Succeed(child) => match tick_behavior_tree(child, ctx) {
+            BtStatus::Running => BtStatus::Running,
+            _ => BtStatus::Success,
+        },
+        BtNode::Condition(condition) => {
+            let passed = match *condition {
+                BtCondition::PlayerVisible => ctx.senses.can_see_player,
+                BtCondition::PlayerWithin(range) => {
+                    ctx.senses.distance_to_player.is_some_and(|d| d <= range)
+                }
+                BtCondition::HealthBelow(ratio) => ctx.senses.health_ratio < ratio,
+                BtCondition::AwayFromHome(range) => ctx.senses.distance_to_home > range,
+            };
+            if passed {
+                BtStatus::Success
+            } else {
+                BtStatus::Failure
+            }
+        }
+        BtNode::Action(action) => {
+            let (status, intent) = match *action {
+                BtAction::ChasePlayer => match ctx.player_center {
+                    Some(p) => (BtStatus::Running, MonsterMoveIntent::Seek(p)),
+                    None => (BtStatus::Failure, MonsterMoveIntent::Hold),
+                },
+                BtAction::FleePlayer => match ctx.player_center {
+                    Some(p) => (BtStatus::Running, MonsterMoveIntent::Flee(p)),
+                    None => (BtStatus::Failure, MonsterMoveIntent::Hold),
+                },
+                BtAction::ReturnHome(tolerance) => {
+                    if ctx.senses.distance_to_home <= tolerance {
+                        (BtStatus::Success, MonsterMoveIntent::Hold)
+                    } else {
+                        (BtStatus::Running, MonsterMoveIntent::Seek(ctx.home_center))
+                    }
+                }
+                BtAction::Wander => (BtStatus::Running, MonsterMoveIntent::Wander),
+                BtAction::Hold => (BtStatus::Running, MonsterMoveIntent::Hold),
+            };
+            // a finished or failed action doesn't get to move the monster
+            if status == BtStatus::Running {
+                ctx.intent = Some(intent);
+            }
+            status
+        }
+    }
+}
+
+// Monster types that are authored as data swap their hard-coded state machine for a tree
+fn monster_attach_behavior_tree(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    // a monster from a saved game comes with its tree
+    monster_query: Query<
+        (Entity, &MonsterArchetype, &MovementCmp),
+        (Added<MonsterArchetype>, Without<BehaviorTreeCmp>),
+    >,
+) {
+    for (monster, archetype, monster_mov) in monster_query.iter() {
+        if let Some(path) = archetype.behavior_tree() {
+            commands
+                .entity(monster)
+                .remove::<MonsterAiCmp>()
+                .insert(BehaviorTreeCmp {
+                    tree: asset_server.load(path),
+                    home: monster_mov.position,
+                });
+        }
+    }
+}
+
+fn monster_behavior_tree(
+    trees: Res<Assets<BehaviorTreeAsset>>,
+    player_query: Query<&RectangleHitboxCmp, With<PlayerEntity>>,
+    mut monster_query: Query<
+        (
+            &BehaviorTreeCmp,
+            &MovementCmp,
+            &RectangleHitboxCmp,
+            Option<&HealthCmp>,
+            &mut SteeringCmp,
+        ),
+        With<MonsterEntity>,
+    >,
+    wall_query: Query<&RectangleHitboxCmp, With<WallEntity>>,
+) {
+    let player_centers: Vec<Point> = player_query
+        .iter()
+        .map(|player_hitbox| player_hitbox.rect.center())
+        .collect();
+
+    for (monster_bt, mut monster_mov, monster_hitbox, monster_health, mut monster_steering) in
+        monster_query.iter_mut()
+    {
+        // still loading
+        let tree = match trees.get(&monster_bt.tree) {
+            Some(tree) => tree,
+            None => continue,
+        };
+
+        let monster_center = monster_hitbox.rect.center();
+        let player_center = nearest_player_center(&monster_center, &player_centers);
+        let senses = sense_monster_surroundings(
+            &monster_center,
+            monster_mov,
+            monster_bt.home,
+            monster_health,
+            player_center,
+            wall_query.iter().map(|wall_hitbox| &wall_hitbox.rect),
+        );
+        let mut ctx = BtContext {
+            senses: &senses,
+            player_center: player_center.map(|p| p.get_vec2()),
+            home_center: monster_bt.home + (monster_center - monster_mov.position).get_vec2(),
+            intent: None,
+        };
+        tick_behavior_tree(&tree.root, &mut ctx);
+
+        apply_monster_move_intent(
+            ctx.intent.unwrap_or(MonsterMoveIntent::Hold),
+            monster_center.get_vec2(),
+            &mut monster_steering,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tick(node: &BtNode) -> BtStatus {
+        tick_with_intent(node).0
+    }
+
+    fn tick_with_intent(node: &BtNode) -> (BtStatus, Option<MonsterMoveIntent>) {
+        let senses = MonsterAiSenses {
+            distance_to_player: Some(100.),
+            can_see_player: true,
+            distance_to_home: 50.,
+            health_ratio: 1.,
+        };
+        let mut ctx = BtContext {
+            senses: &senses,
+            player_center: Some(Vec2::new(100., 0.)),
+            home_center: Vec2::new(0., 0.),
+            intent: None,
+        };
+        let status = tick_behavior_tree(node, &mut ctx);
+        (status, ctx.intent)
+    }
+
+    fn success() -> BtNode {
+        BtNode::Condition(BtCondition::PlayerVisible)
+    }
+
+    fn failure() -> BtNode {
+        BtNode::Condition(BtCondition::HealthBelow(0.5))
+    }
+
+    fn running() -> BtNode {
+        BtNode::Action(BtAction::Hold)
+    }
+
+    #[test]
+    fn sequence_stops_at_the_first_child_that_does_not_succeed() {
+        use BtNode::Sequence;
+        assert_eq!(
+            tick(&Sequence(vec![success(), success()])),
+            BtStatus::Success
+        );
+        assert_eq!(
+            tick(&Sequence(vec![success(), failure(), running()])),
+            BtStatus::Failure
+        );
+        assert_eq!(
+            tick(&Sequence(vec![running(), failure()])),
+            BtStatus::Running
+        );
+        assert_eq!(tick(&Sequence(vec![])), BtStatus::Success);
+    }
+
+    #[test]
+    fn selector_stops_at_the_first_child_that_does_not_fail() {
+        use BtNode::Selector;
+        assert_eq!(
+            tick(&Selector(vec![failure(), success()])),
+            BtStatus::Success
+        );
+        assert_eq!(
+            tick(&Selector(vec![failure(), failure()])),
+            BtStatus::Failure
+        );
+        assert_eq!(
+            tick(&Selector(vec![failure(), running(), success()])),
+            BtStatus::Running
+        );
+        assert_eq!(tick(&Selector(vec![])), BtStatus::Failure);
+    }
+
+    #[test]
+    fn invert_swaps_success_and_failure() {
+        use BtNode::Invert;
+        assert_eq!(tick(&Invert(Box::new(success()))), BtStatus::Failure);
+        assert_eq!(tick(&Invert(Box::new(failure()))), BtStatus::Success);
+        assert_eq!(tick(&Invert(Box::new(running()))), BtStatus::Running);
+    }
+
+    #[test]
+    fn succeed_only_keeps_running() {
+        use BtNode::Succeed;
+        assert_eq!(tick(&Succeed(Box::new(success()))), BtStatus::Success);
+        assert_eq!(tick(&Succeed(Box::new(failure()))), BtStatus::Success);
+        assert_eq!(tick(&Succeed(Box::new(running()))), BtStatus::Running);
+    }
+
+    #[test]
+    fn only_a_running_action_sets_the_intent() {
+        use BtNode::{Action, Selector, Sequence};
+        // the monster is 50 away from home
+        let home = || Action(BtAction::ReturnHome(60.));
+        let (status, intent) = tick_with_intent(&Selector(vec![home(), running()]));
+        assert_eq!(status, BtStatus::Success);
+        assert!(intent.is_none());
+
+        let (status, intent) = tick_with_intent(&Sequence(vec![home(), Action(BtAction::Wander)]));
+        assert_eq!(status, BtStatus::Running);
+        assert!(matches!(intent, Some(MonsterMoveIntent::Wander)));
+
+        let (status, intent) = tick_with_intent(&Action(BtAction::ReturnHome(10.)));
+        assert_eq!(status, BtStatus::Running);
+        assert!(matches!(intent, Some(MonsterMoveIntent::Seek(_))));
+    }
+}
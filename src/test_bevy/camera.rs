@@ -0,0 +1,145 @@
+use bevy::input::*;
+use bevy::prelude::*;
+use bevy::window::*;
+
+use crate::test_bevy::*;
+
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_set(
+            SystemSet::on_update(AppState::Playing).with_system(
+                camera_follow
+                    .system()
+                    .label("camera_follow_system")
+                    .after("player_update_hitbox_system")
+                    .after("level_place_player_system"),
+            ),
+        );
+    }
+}
+
+pub struct MainCameraEntity;
+
+// How the camera chases the player
+pub struct CameraFollowCmp {
+    // half size of the box around the screen center the player can move in without the camera
+    // following
+    pub deadzone: Vec2,
+    // how fast the camera catches up, 0 snaps right onto the target
+    pub smoothing: f32,
+    // never show what's outside the current map
+    pub clamp_to_map: bool,
+    // room kept between the players and the screen edges when zooming out to fit them all
+    pub margin: f32,
+}
+
+impl CameraFollowCmp {
+    pub fn new(config: &CameraConfig) -> Self {
+        Self {
+            deadzone: config.deadzone,
+            smoothing: config.smoothing,
+            clamp_to_map: true,
+            margin: config.margin,
+        }
+    }
+}
+
+// Where the camera has to be (in bevy coordinates) so the target sits on the deadzone's edge
+pub fn camera_deadzone_target(camera: Vec2, target: Vec2, deadzone: Vec2) -> Vec2 {
+    let offset = target - camera;
+    let overshoot = offset - offset.max(-deadzone).min(deadzone);
+    camera + overshoot
+}
+
+// Keeps the view (view_size, centered on camera) inside min..max, a map smaller than the view
+// gets centered instead
+pub fn camera_clamp(camera: Vec2, view_size: Vec2, min: Vec2, max: Vec2) -> Vec2 {
+    let clamp_axis = |camera: f32, view: f32, min: f32, max: f32| {
+        if max - min <= view {
+            (min + max) / 2.
+        } else {
+            camera.max(min + view / 2.).min(max - view / 2.)
+        }
+    };
+    Vec2::new(
+        clamp_axis(camera.x, view_size.x, min.x, max.x),
+        clamp_axis(camera.y, view_size.y, min.y, max.y),
+    )
+}
+
+// How far the camera zooms out (1 is no zoom) so players spread over extent (their bounding box)
+// still fit in the view, wherever they are in the deadzone
+pub fn camera_fit_zoom(extent: Vec2, view_size: Vec2, deadzone: Vec2, margin: f32) -> f32 {
+    let needed = extent + (deadzone + Vec2::splat(margin)) * 2.;
+    (needed / view_size).max_element().max(1.)
+}
+
+fn camera_follow(
+    time: Res<Time>,
+    win_size: Res<GameWindowSize>,
+    maps: Res<Assets<Map>>,
+    current_map: Option<Res<CurrentMap>>,
+    mut map_spawned_events: EventReader<MapSpawnedEvent>,
+    player_query: Query<&RectangleHitboxCmp, With<PlayerEntity>>,
+    new_player_query: Query<Entity, Added<PlayerEntity>>,
+    mut camera_query: Query<(&CameraFollowCmp, &mut Transform), With<MainCameraEntity>>,
+) {
+    // a new level (or a new player) is not a place to glide through
+    let snap = map_spawned_events.iter().count() > 0 || new_player_query.iter().next().is_some();
+    if let Ok((camera_follow, mut camera_tf)) = camera_query.single_mut() {
+        let camera = Vec2::new(camera_tf.translation.x, camera_tf.translation.y);
+        let zoom = camera_tf.scale.x;
+        let win = Vec2::new(win_size.w, win_size.h);
+        // the box around all players, they share the screen
+        let corners: Vec<(Vec2, Vec2)> = player_query
+            .iter()
+            .map(|player_hitbox| {
+                let rect = &player_hitbox.rect;
+                let a = world_to_bevy(&rect.upper_left).get_vec2();
+                let b = world_to_bevy(&rect.lower_right).get_vec2();
+                (a.min(b), a.max(b))
+            })
+            .collect();
+        let (mut desired, desired_zoom) = match corners.first() {
+            None => (camera, zoom),
+            Some(first) => {
+                let (min, max) = corners
+                    .iter()
+                    .fold(*first, |(min, max), (a, b)| (min.min(*a), max.max(*b)));
+                let target = (min + max) / 2.;
+                (
+                    camera_deadzone_target(camera, target, camera_follow.deadzone),
+                    camera_fit_zoom(max - min, win, camera_follow.deadzone, camera_follow.margin),
+                )
+            }
+        };
+
+        let map = current_map.and_then(|current_map| maps.get(&current_map.0));
+        if let (true, Some(map)) = (camera_follow.clamp_to_map, map) {
+            // top-left and bottom-right corners of the map, y flips so they become min and max
+            let topleft = world_to_bevy(&Point::new(map.topleft())).get_vec2();
+            let botright = world_to_bevy(&Point::new(map.topleft() + map.world_size())).get_vec2();
+            desired = camera_clamp(
+                desired,
+                win * desired_zoom,
+                Vec2::new(topleft.x, botright.y),
+                Vec2::new(botright.x, topleft.y),
+            );
+        }
+
+        let (new_camera, new_zoom) = if snap || camera_follow.smoothing <= 0. {
+            (desired, desired_zoom)
+        } else {
+            // frame rate independent exponential smoothing
+            let t = 1. - (-camera_follow.smoothing * time.delta_seconds()).exp();
+            (camera.lerp(desired, t), zoom + (desired_zoom - zoom) * t)
+        };
+        camera_tf.translation.x = new_camera.x;
+        camera_tf.translation.y = new_camera.y;
+        // the orthographic projection shows scale times more of the world
+        camera_tf.scale.x = new_zoom;
+        camera_tf.scale.y = new_zoom;
+    }
+}
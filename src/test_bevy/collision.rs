@@ -1,5 +1,4 @@
 #[macro_use]
-use bevy;
 use bevy::prelude::*;
 use fstrings::*;
 use std::{fmt, mem::swap, ops};
@@ -130,6 +129,10 @@ impl Rect {
         self.width * self.height
     }
 
+    pub fn center(&self) -> Point {
+        self.upper_left + Vec2::new(self.width / 2., self.height / 2.)
+    }
+
     pub fn r#move(&mut self, delta_x: f32, delta_y: f32) {
         let delta = Point::new((delta_x, delta_y));
         self.upper_left = self.upper_left + delta;
@@ -159,6 +162,14 @@ impl Rect {
             || self.is_point_inside(&r2_ll)
     }
 
+    // Unlike is_rect_intersect this also catches r2 fully covering self
+    pub fn is_rect_overlap(&self, r2: &Rect) -> bool {
+        self.upper_left.x() < r2.lower_right.x()
+            && self.lower_right.x() > r2.upper_left.x()
+            && self.upper_left.y() < r2.lower_right.y()
+            && self.lower_right.y() > r2.upper_left.y()
+    }
+
     // (Arbitrary Rectangle Collision Detection & Resolution - Complete!)
     // https://www.youtube.com/watch?v=8JJ-4JgR7Dg
     pub fn is_ray_intersect(
@@ -209,7 +220,7 @@ impl Rect {
             return (false, None, None, None);
         }
 
-        contact_point.update((ray.start + ray.end * t_hit_near));
+        contact_point.update(ray.start + ray.end * t_hit_near);
 
         if near_x > near_y {
             if ray_len_x < 0. {
@@ -242,12 +253,12 @@ impl Rect {
             // dbg!((near_x, near_y, far_x, far_y, ray_len_x, ray_len_y));
         }
 
-        return (
+        (
             true,
             Some(contact_point),
             Some(contact_normal),
             Some(t_hit_near),
-        );
+        )
     }
 
     pub fn is_rect_collide(
@@ -283,12 +294,39 @@ impl Rect {
         let res = collided && (contact_time.unwrap() >= 0.0 && contact_time.unwrap() <= 1.0);
 
         if res {
-            return (true, contact_point, contact_normal, contact_time);
+            (true, contact_point, contact_normal, contact_time)
         } else {
-            return (false, None, None, None);
+            (false, None, None, None)
         }
     }
 }
+// Returns true if the segment from -> to passes through any of the obstacles
+pub fn is_line_of_sight_blocked<'a, I>(from: &Point, to: &Point, obstacles: I) -> bool
+where
+    I: IntoIterator<Item = &'a Rect>,
+{
+    let delta = to.0 - from.0;
+    obstacles.into_iter().any(|rect| {
+        // slab test, t in 0..1 along the segment
+        let near = (rect.upper_left.0 - from.0) / delta;
+        let far = (rect.lower_right.0 - from.0) / delta;
+        let slab = |near: f32, far: f32| {
+            // 0 / 0: a segment parallel to the axis runs right along an edge, which counts as
+            // a hit as long as the other axis overlaps
+            if near.is_nan() || far.is_nan() {
+                (f32::NEG_INFINITY, f32::INFINITY)
+            } else {
+                (near.min(far), near.max(far))
+            }
+        };
+        let (near_x, far_x) = slab(near.x, far.x);
+        let (near_y, far_y) = slab(near.y, far.y);
+        let t_enter = near_x.max(near_y);
+        let t_exit = far_x.min(far_y);
+        t_enter <= t_exit && t_exit >= 0. && t_enter <= 1.
+    })
+}
+
 impl fmt::Display for Rect {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write_f!(f, "{{ ul: {self.upper_left}, lr: {self.lower_right}, w: {self.width}, h: {self.height} }}")
@@ -0,0 +1,359 @@
+use std::path::{Path, PathBuf};
+
+use bevy::input::*;
+use bevy::prelude::*;
+use bevy::window::*;
+
+use crate::test_bevy::*;
+
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(MapEditor::default())
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .with_system(editor_toggle.system().label("editor_toggle_system"))
+                    .with_system(
+                        editor_keys
+                            .system()
+                            .label("editor_keys_system")
+                            .after("editor_toggle_system"),
+                    )
+                    .with_system(
+                        editor_paint
+                            .system()
+                            .label("editor_paint_system")
+                            .after("editor_keys_system"),
+                    )
+                    .with_system(editor_cursor.system().after("editor_paint_system")),
+            )
+            .add_system_set(
+                SystemSet::on_exit(AppState::Playing).with_system(editor_teardown.system()),
+            );
+    }
+}
+
+// the map saved when the current one doesn't come from a file (e.g. a generated one)
+const EDITOR_DEFAULT_PATH: &str = "maps/edited.map";
+
+#[derive(Debug, Clone, Copy)]
+pub struct MapEdit {
+    col: usize,
+    row: usize,
+    before: char,
+    after: char,
+}
+
+// F2 toggles the editor. While it's open:
+// - left click paints the selected glyph, right click erases, a whole drag is one undo step
+// - 1..9 and 0 pick the glyph from the palette (the legend of the map, sorted)
+// - Ctrl+Z undoes, Ctrl+Y or Ctrl+Shift+Z redoes
+// - Ctrl+S saves the map back to its .map file
+#[derive(Default)]
+pub struct MapEditor {
+    pub active: bool,
+    palette: Vec<char>,
+    selected: usize,
+    grid: Vec<Vec<char>>,
+    // glyph of TileKind::Empty in the legend of the map
+    erase_glyph: char,
+    // edits of the drag in progress
+    stroke: Vec<MapEdit>,
+    undo: Vec<Vec<MapEdit>>,
+    redo: Vec<Vec<MapEdit>>,
+}
+
+impl MapEditor {
+    fn open(&mut self, map: &Map) {
+        let legend = map.legend();
+        self.erase_glyph = legend
+            .0
+            .iter()
+            .find(|(_, kind)| **kind == TileKind::Empty)
+            .map_or(' ', |(glyph, _)| *glyph);
+        self.palette = legend
+            .0
+            .iter()
+            .filter(|(_, kind)| **kind != TileKind::Empty)
+            .map(|(glyph, _)| *glyph)
+            .collect();
+        self.palette.sort();
+        self.selected = 0;
+        self.grid = map.grid();
+        self.stroke.clear();
+        self.undo.clear();
+        self.redo.clear();
+        self.active = true;
+    }
+
+    pub fn selected_glyph(&self) -> Option<char> {
+        self.palette.get(self.selected).copied()
+    }
+
+    // records the change into the current stroke, false if the cell already had the glyph
+    fn set_cell(&mut self, col: usize, row: usize, glyph: char) -> bool {
+        let cell = match self
+            .grid
+            .get_mut(row)
+            .and_then(|grid_row| grid_row.get_mut(col))
+        {
+            Some(cell) => cell,
+            None => return false,
+        };
+        if *cell == glyph {
+            return false;
+        }
+        self.stroke.push(MapEdit {
+            col,
+            row,
+            before: *cell,
+            after: glyph,
+        });
+        *cell = glyph;
+        true
+    }
+
+    fn end_stroke(&mut self) {
+        if !self.stroke.is_empty() {
+            self.undo.push(std::mem::take(&mut self.stroke));
+            self.redo.clear();
+        }
+    }
+
+    fn undo(&mut self) -> bool {
+        self.end_stroke();
+        match self.undo.pop() {
+            Some(edits) => {
+                for edit in edits.iter().rev() {
+                    self.grid[edit.row][edit.col] = edit.before;
+                }
+                self.redo.push(edits);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn redo(&mut self) -> bool {
+        match self.redo.pop() {
+            Some(edits) => {
+                for edit in edits.iter() {
+                    self.grid[edit.row][edit.col] = edit.after;
+                }
+                self.undo.push(edits);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+// The square that shows which cell a click would paint
+pub struct EditorCursorEntity;
+
+fn editor_close(
+    commands: &mut Commands,
+    editor: &mut MapEditor,
+    cursor_query: &Query<Entity, With<EditorCursorEntity>>,
+) {
+    editor.end_stroke();
+    editor.active = false;
+    for cursor in cursor_query.iter() {
+        commands.entity(cursor).despawn();
+    }
+    println!("Map editor closed");
+}
+
+fn editor_toggle(
+    mut commands: Commands,
+    keyboard_input: Res<Input<KeyCode>>,
+    materials: Res<Materials>,
+    maps: Res<Assets<Map>>,
+    current_map: Res<CurrentMap>,
+    mut editor: ResMut<MapEditor>,
+    cursor_query: Query<Entity, With<EditorCursorEntity>>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::F2) {
+        return;
+    }
+    if editor.active {
+        editor_close(&mut commands, &mut editor, &cursor_query);
+        return;
+    }
+    if let Some(map) = maps.get(&current_map.0) {
+        editor.open(map);
+        commands
+            .spawn_bundle(tile_sprite(
+                &materials.editor_cursor_materials,
+                &Point::new(map.topleft()),
+                map.block_size(),
+                5.,
+            ))
+            .insert(EditorCursorEntity);
+        println!(
+            "Map editor opened on {}, palette {:?}",
+            map.name(),
+            editor.palette
+        );
+    }
+}
+
+// the map being edited goes away with the game
+fn editor_teardown(
+    mut commands: Commands,
+    mut editor: ResMut<MapEditor>,
+    cursor_query: Query<Entity, With<EditorCursorEntity>>,
+) {
+    if editor.active {
+        editor_close(&mut commands, &mut editor, &cursor_query);
+    }
+}
+
+fn editor_keys(
+    keyboard_input: Res<Input<KeyCode>>,
+    asset_server: Res<AssetServer>,
+    mut maps: ResMut<Assets<Map>>,
+    current_map: Res<CurrentMap>,
+    mut editor: ResMut<MapEditor>,
+) {
+    if !editor.active {
+        return;
+    }
+    let palette_keys = [
+        KeyCode::Key1,
+        KeyCode::Key2,
+        KeyCode::Key3,
+        KeyCode::Key4,
+        KeyCode::Key5,
+        KeyCode::Key6,
+        KeyCode::Key7,
+        KeyCode::Key8,
+        KeyCode::Key9,
+        KeyCode::Key0,
+    ];
+    for (idx, key) in palette_keys.iter().enumerate() {
+        if keyboard_input.just_pressed(*key) && idx < editor.palette.len() {
+            editor.selected = idx;
+            println!("Painting {:?}", editor.palette[idx]);
+        }
+    }
+
+    let ctrl =
+        keyboard_input.pressed(KeyCode::LControl) || keyboard_input.pressed(KeyCode::RControl);
+    let shift = keyboard_input.pressed(KeyCode::LShift) || keyboard_input.pressed(KeyCode::RShift);
+    if !ctrl {
+        return;
+    }
+    let changed = if keyboard_input.just_pressed(KeyCode::Z) && !shift {
+        editor.undo()
+    } else if keyboard_input.just_pressed(KeyCode::Y)
+        || (keyboard_input.just_pressed(KeyCode::Z) && shift)
+    {
+        editor.redo()
+    } else {
+        false
+    };
+    if changed {
+        if let Some(map) = maps.get_mut(&current_map.0) {
+            *map = map.with_grid(&editor.grid);
+        }
+    }
+
+    if keyboard_input.just_pressed(KeyCode::S) {
+        editor.end_stroke();
+        let map = match maps.get(&current_map.0) {
+            Some(map) => map,
+            None => return,
+        };
+        let text = map_to_string(map);
+        // saving a broken map would only make the loader fail on the next start
+        if let Err(err) = parse_map(&text) {
+            println!("Can't save {}: {}", map.name(), err);
+            return;
+        }
+        let path = asset_server.get_handle_path(&current_map.0).map_or_else(
+            || PathBuf::from(EDITOR_DEFAULT_PATH),
+            // an imported Tiled map gets an ASCII copy next to it
+            |asset_path| map_save_path(asset_path.path()),
+        );
+        let path = PathBuf::from("assets").join(path);
+        match std::fs::write(&path, text) {
+            Ok(()) => println!("Saved {} to {}", map.name(), path.display()),
+            Err(err) => println!("Can't save {} to {}: {}", map.name(), path.display(), err),
+        }
+    }
+}
+
+// x.map for x.map as well as x.tiled.json, the whole extension goes
+fn map_save_path(path: &Path) -> PathBuf {
+    let stem = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.split('.').next())
+        .unwrap_or_default();
+    path.with_file_name(stem).with_extension("map")
+}
+
+fn editor_paint(
+    mouse_input: Res<Input<MouseButton>>,
+    cursor_pos: Res<CursorPosition>,
+    mut maps: ResMut<Assets<Map>>,
+    current_map: Res<CurrentMap>,
+    mut editor: ResMut<MapEditor>,
+) {
+    if !editor.active {
+        return;
+    }
+    if mouse_input.just_released(MouseButton::Left) || mouse_input.just_released(MouseButton::Right)
+    {
+        editor.end_stroke();
+    }
+    let glyph = if mouse_input.pressed(MouseButton::Left) {
+        editor.selected_glyph()
+    } else if mouse_input.pressed(MouseButton::Right) {
+        Some(editor.erase_glyph)
+    } else {
+        None
+    };
+    let (glyph, cursor) = match (glyph, cursor_pos.x, cursor_pos.y) {
+        (Some(glyph), Some(x), Some(y)) => (glyph, Vec2::new(x, y)),
+        _ => return,
+    };
+    let cell = match maps.get(&current_map.0).and_then(|map| map.cell_at(cursor)) {
+        Some(cell) => cell,
+        None => return,
+    };
+    // only touch the asset when something changed, every change respawns the map
+    if editor.set_cell(cell.0, cell.1, glyph) {
+        if let Some(map) = maps.get_mut(&current_map.0) {
+            *map = map.with_grid(&editor.grid);
+        }
+    }
+}
+
+fn editor_cursor(
+    cursor_pos: Res<CursorPosition>,
+    maps: Res<Assets<Map>>,
+    current_map: Res<CurrentMap>,
+    mut cursor_query: Query<(&mut Transform, &mut Visible), With<EditorCursorEntity>>,
+) {
+    if let Ok((mut cursor_tf, mut cursor_visible)) = cursor_query.single_mut() {
+        let map = match maps.get(&current_map.0) {
+            Some(map) => map,
+            None => return,
+        };
+        let cell = match (cursor_pos.x, cursor_pos.y) {
+            (Some(x), Some(y)) => map.cell_at(Vec2::new(x, y)),
+            _ => None,
+        };
+        cursor_visible.is_visible = cell.is_some();
+        if let Some((col, row)) = cell {
+            let half_block = map.block_size() / 2.;
+            let sp = Point::new(map.cell_topleft(col, row) + half_block);
+            let bevy_sp = world_to_bevy(&sp);
+            cursor_tf.translation.x = bevy_sp.x();
+            cursor_tf.translation.y = bevy_sp.y();
+        }
+    }
+}
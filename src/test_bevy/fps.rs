@@ -5,15 +5,15 @@ use bevy::{
 
 use crate::test_bevy::*;
 
-/// This example illustrates how to create UI text and update it in a system. It displays the
-/// current FPS in the top left corner, as well as text that changes colour in the bottom right.
-/// For text within a scene, please see the text2d example.
+// This example illustrates how to create UI text and update it in a system. It displays the
+// current FPS in the top left corner, as well as text that changes colour in the bottom right.
+// For text within a scene, please see the text2d example.
 
 pub struct FpsPlugin;
 
 impl Plugin for FpsPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_plugin(FrameTimeDiagnosticsPlugin::default())
+        app.add_plugin(FrameTimeDiagnosticsPlugin)
             .add_startup_system(setup.system())
             .add_system(text_update_system.system())
             .add_system(text_color_system.system());
@@ -1,6 +1,549 @@
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
 
+use bevy::input::*;
+use bevy::prelude::*;
+use bevy::window::*;
 
+use crate::test_bevy::*;
+
+// Watches the config file and swaps in a new GlobalConfig whenever it changes and still passes
+// validation. The window, the clear color, the camera and the materials follow right away, sizes
+// and speeds only apply to what spawns afterwards and the seed to the next launch.
+pub struct GlobalConfigPlugin;
+
+impl Plugin for GlobalConfigPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(GlobalConfigWatch::new(global_config_path()))
+            .add_system(config_reload.system().label("config_reload_system"))
+            .add_system(config_apply.system().after("config_reload_system"));
+    }
+}
+
+pub const GLOBAL_CONFIG_PATH: &str = "assets/config/game.toml";
+// how often the file is checked for changes, in seconds
+const CONFIG_WATCH_INTERVAL: f32 = 1.;
+
+// --config <file> on the command line, assets/config/game.toml otherwise
+pub fn global_config_path() -> String {
+    let args: Vec<String> = std::env::args().collect();
+    args.iter()
+        .position(|arg| arg == "--config")
+        .and_then(|idx| args.get(idx + 1))
+        .cloned()
+        .unwrap_or_else(|| STR(GLOBAL_CONFIG_PATH))
+}
+
+// The tunables of the game as written in assets/config/game.toml. Every key is optional, the
+// ones left out keep the values below. For example:
+//   window_title = "Example Breakout"
+//   player_hitbox_size = [35.0, 35.0]
+//
+//   [monster_speed]
+//   grunt = 600.0
+//
+//   [camera]
+//   deadzone = [60.0, 40.0]
+//
+//   [colors]
+//   monster = [0.2, 0.2, 0.7, 1.0]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct GlobalConfig {
-    player_hitbox_size: Vec2,
-    
-}
\ No newline at end of file
+    // of the GameRng, --seed wins over it. A fresh one every run if there is neither
+    pub seed: Option<u64>,
+    pub window_title: String,
+    pub window_width: f32,
+    pub window_height: f32,
+    pub player_hitbox_size: Vec2,
+    // px/s
+    pub player_speed: f32,
+    // px/s² while there is input / while there is none
+    pub player_acceleration: f32,
+    pub player_friction: f32,
+    pub monster_hitbox_size: Vec2,
+    pub monster_speed: MonsterSpeedConfig,
+    pub dash: DashConfig,
+    pub camera: CameraConfig,
+    pub generated_maps: GeneratedMapConfig,
+    pub colors: ColorConfig,
+}
+
+impl Default for GlobalConfig {
+    fn default() -> Self {
+        Self {
+            seed: None,
+            window_title: STR("Example Breakout"),
+            window_width: 600.,
+            window_height: 600.,
+            player_hitbox_size: Vec2::new(35., 35.),
+            player_speed: 800.,
+            player_acceleration: 6000.,
+            player_friction: 9000.,
+            monster_hitbox_size: Vec2::new(35., 35.),
+            monster_speed: MonsterSpeedConfig::default(),
+            dash: DashConfig::default(),
+            camera: CameraConfig::default(),
+            generated_maps: GeneratedMapConfig::default(),
+            colors: ColorConfig::default(),
+        }
+    }
+}
+
+// px/s of each archetype
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MonsterSpeedConfig {
+    pub grunt: f32,
+    pub coward: f32,
+    pub berserker: f32,
+    pub stalker: f32,
+    pub skirmisher: f32,
+}
+
+impl Default for MonsterSpeedConfig {
+    fn default() -> Self {
+        Self {
+            grunt: 600.,
+            coward: 700.,
+            berserker: 450.,
+            stalker: 500.,
+            skirmisher: 650.,
+        }
+    }
+}
+
+// The dash of the players
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DashConfig {
+    // px/s
+    pub speed: f32,
+    // seconds
+    pub duration: f32,
+    pub cooldown: f32,
+    pub iframes: f32,
+}
+
+impl Default for DashConfig {
+    fn default() -> Self {
+        Self {
+            speed: 2000.,
+            duration: 0.12,
+            cooldown: 0.6,
+            iframes: 0.25,
+        }
+    }
+}
+
+// How the camera follows the players, see CameraFollowCmp
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraConfig {
+    pub deadzone: Vec2,
+    pub smoothing: f32,
+    pub margin: f32,
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        Self {
+            deadzone: Vec2::new(60., 40.),
+            smoothing: 8.,
+            margin: 40.,
+        }
+    }
+}
+
+// The levels that come out of the map generators
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GeneratedMapConfig {
+    // in cells, bigger than the window so the camera follows the player around
+    pub size: (usize, usize),
+    pub block_size: Vec2,
+    pub topleft: Vec2,
+}
+
+impl Default for GeneratedMapConfig {
+    fn default() -> Self {
+        Self {
+            size: (48, 48),
+            block_size: Vec2::new(20., 20.),
+            topleft: Vec2::new(40., 40.),
+        }
+    }
+}
+
+// rgba, 0..1 each. The wall colors tint the wall atlas.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColorConfig {
+    pub clear: [f32; 4],
+    // one per local player
+    pub players: Vec<[f32; 4]>,
+    pub monster: [f32; 4],
+    pub wall: [f32; 4],
+    pub stop_wall: [f32; 4],
+    pub reflect_wall: [f32; 4],
+    pub destructible_wall: [f32; 4],
+    pub door: [f32; 4],
+    pub pickup: [f32; 4],
+    pub trigger: [f32; 4],
+    pub exit: [f32; 4],
+    pub ray: [f32; 4],
+    pub projectile: [f32; 4],
+    pub editor_cursor: [f32; 4],
+    pub screen: [f32; 4],
+}
+
+impl Default for ColorConfig {
+    fn default() -> Self {
+        Self {
+            clear: [0., 0., 0., 1.],
+            players: vec![
+                [1., 0.7, 0.7, 1.],
+                [0.6, 0.9, 1., 1.],
+                [0.7, 1., 0.6, 1.],
+                [1., 0.95, 0.5, 1.],
+            ],
+            monster: [0.2, 0.2, 0.7, 1.],
+            wall: [1., 1., 1., 1.],
+            stop_wall: [0.6, 0.6, 0.6, 1.],
+            reflect_wall: [0.7, 0.9, 1., 1.],
+            destructible_wall: [0.6, 0.4, 0.2, 1.],
+            door: [0.8, 0.6, 0.1, 1.],
+            pickup: [0.2, 0.9, 0.3, 1.],
+            trigger: [0.9, 0.2, 0.9, 0.3],
+            exit: [0.2, 0.9, 0.9, 0.6],
+            ray: [1., 1., 1., 1.],
+            projectile: [1., 0.9, 0.3, 1.],
+            editor_cursor: [1., 1., 0., 0.4],
+            screen: [0., 0., 0., 0.6],
+        }
+    }
+}
+
+impl ColorConfig {
+    pub fn color(rgba: [f32; 4]) -> Color {
+        Color::rgba(rgba[0], rgba[1], rgba[2], rgba[3])
+    }
+
+    // (name, color) of every single color, for the validation
+    fn named(&self) -> Vec<(String, [f32; 4])> {
+        let mut colors = vec![
+            (STR("clear"), self.clear),
+            (STR("monster"), self.monster),
+            (STR("wall"), self.wall),
+            (STR("stop_wall"), self.stop_wall),
+            (STR("reflect_wall"), self.reflect_wall),
+            (STR("destructible_wall"), self.destructible_wall),
+            (STR("door"), self.door),
+            (STR("pickup"), self.pickup),
+            (STR("trigger"), self.trigger),
+            (STR("exit"), self.exit),
+            (STR("ray"), self.ray),
+            (STR("projectile"), self.projectile),
+            (STR("editor_cursor"), self.editor_cursor),
+            (STR("screen"), self.screen),
+        ];
+        for (idx, player) in self.players.iter().enumerate() {
+            colors.push((format!("players[{}]", idx), *player));
+        }
+        colors
+    }
+}
+
+impl GlobalConfig {
+    // the defaults when the file is missing or invalid, like the input bindings
+    pub fn load(path: &str) -> Self {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(_) => return Self::default(),
+        };
+        match Self::parse(&text) {
+            Ok(config) => config,
+            Err(err) => {
+                println!("Can't read the config from {}: {}", path, err);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn from_args() -> Self {
+        Self::load(&global_config_path())
+    }
+
+    pub fn parse(text: &str) -> Result<Self, anyhow::Error> {
+        let config: GlobalConfig = toml::from_str(text)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if self.window_title.trim().is_empty() {
+            anyhow::bail!("window_title is empty");
+        }
+        let positive = [
+            ("window_width", self.window_width),
+            ("window_height", self.window_height),
+            ("player_hitbox_size", self.player_hitbox_size.min_element()),
+            ("player_speed", self.player_speed),
+            ("player_acceleration", self.player_acceleration),
+            ("player_friction", self.player_friction),
+            (
+                "monster_hitbox_size",
+                self.monster_hitbox_size.min_element(),
+            ),
+            ("monster_speed.grunt", self.monster_speed.grunt),
+            ("monster_speed.coward", self.monster_speed.coward),
+            ("monster_speed.berserker", self.monster_speed.berserker),
+            ("monster_speed.stalker", self.monster_speed.stalker),
+            ("monster_speed.skirmisher", self.monster_speed.skirmisher),
+            ("dash.speed", self.dash.speed),
+            ("dash.duration", self.dash.duration),
+            (
+                "generated_maps.block_size",
+                self.generated_maps.block_size.min_element(),
+            ),
+        ];
+        for (name, value) in positive.iter() {
+            // NaN isn't positive either
+            if value.is_nan() || *value <= 0. {
+                anyhow::bail!("{} has to be positive, got {}", name, value);
+            }
+        }
+        let non_negative = [
+            ("dash.cooldown", self.dash.cooldown),
+            ("dash.iframes", self.dash.iframes),
+            ("camera.deadzone", self.camera.deadzone.min_element()),
+            ("camera.smoothing", self.camera.smoothing),
+            ("camera.margin", self.camera.margin),
+        ];
+        for (name, value) in non_negative.iter() {
+            if value.is_nan() || *value < 0. {
+                anyhow::bail!("{} can't be negative, got {}", name, value);
+            }
+        }
+        let (width, height) = self.generated_maps.size;
+        if width.min(height) < MIN_GENERATED_SIZE {
+            anyhow::bail!(
+                "generated_maps.size has to be at least {}x{}, got {}x{}",
+                MIN_GENERATED_SIZE,
+                MIN_GENERATED_SIZE,
+                width,
+                height
+            );
+        }
+        let topleft = self.generated_maps.topleft;
+        if !topleft.x.is_finite() || !topleft.y.is_finite() {
+            anyhow::bail!("generated_maps.topleft has to be finite, got {}", topleft);
+        }
+        if self.colors.players.is_empty() {
+            anyhow::bail!("colors.players needs at least one color");
+        }
+        for (name, rgba) in self.colors.named() {
+            if rgba.iter().any(|channel| !(0. ..=1.).contains(channel)) {
+                anyhow::bail!("colors.{} has to be within 0..1, got {:?}", name, rgba);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn window_descriptor(&self) -> WindowDescriptor {
+        WindowDescriptor {
+            title: self.window_title.clone(),
+            width: self.window_width,
+            height: self.window_height,
+            ..Default::default()
+        }
+    }
+
+    pub fn monster_speed(&self, archetype: MonsterArchetype) -> Vec2 {
+        let speed = match archetype {
+            MonsterArchetype::Grunt => self.monster_speed.grunt,
+            MonsterArchetype::Coward => self.monster_speed.coward,
+            MonsterArchetype::Berserker => self.monster_speed.berserker,
+            MonsterArchetype::Stalker => self.monster_speed.stalker,
+            MonsterArchetype::Skirmisher => self.monster_speed.skirmisher,
+        };
+        Vec2::new(speed, speed)
+    }
+
+    pub fn player_color(&self, player: usize) -> Color {
+        ColorConfig::color(self.colors.players[player % self.colors.players.len()])
+    }
+}
+
+pub struct GlobalConfigWatch {
+    pub path: String,
+    modified: Option<SystemTime>,
+    timer: Timer,
+}
+
+impl GlobalConfigWatch {
+    pub fn new(path: String) -> Self {
+        let modified = config_modified(&path);
+        Self {
+            path,
+            modified,
+            timer: Timer::from_seconds(CONFIG_WATCH_INTERVAL, true),
+        }
+    }
+}
+
+fn config_modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+fn config_reload(
+    time: Res<Time>,
+    playback: Option<Res<ReplayPlayback>>,
+    mut watch: ResMut<GlobalConfigWatch>,
+    mut config: ResMut<GlobalConfig>,
+) {
+    // a replay plays with the config it started with
+    if playback.is_some() {
+        return;
+    }
+    watch.timer.tick(time.delta());
+    if !watch.timer.just_finished() {
+        return;
+    }
+    let modified = config_modified(&watch.path);
+    if modified == watch.modified {
+        return;
+    }
+    watch.modified = modified;
+    let text = match std::fs::read_to_string(&watch.path) {
+        Ok(text) => text,
+        // e.g. deleted, the game keeps what it has
+        Err(_) => return,
+    };
+    // a broken edit keeps the last good config
+    match GlobalConfig::parse(&text) {
+        Ok(reloaded) if reloaded != *config => {
+            println!("Reloaded the config from {}", watch.path);
+            *config = reloaded;
+        }
+        Ok(_) => {}
+        Err(err) => println!("Can't reload the config from {}: {}", watch.path, err),
+    }
+}
+
+fn config_apply(
+    config: Res<GlobalConfig>,
+    materials: Option<Res<Materials>>,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+    mut clear_color: ResMut<ClearColor>,
+    mut windows: ResMut<Windows>,
+    mut camera_query: Query<&mut CameraFollowCmp>,
+) {
+    // the startup already used it
+    if !config.is_changed() || config.is_added() {
+        return;
+    }
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_title(config.window_title.clone());
+        window.set_resolution(config.window_width, config.window_height);
+    }
+    clear_color.0 = ColorConfig::color(config.colors.clear);
+    for mut camera_follow in camera_query.iter_mut() {
+        camera_follow.deadzone = config.camera.deadzone;
+        camera_follow.smoothing = config.camera.smoothing;
+        camera_follow.margin = config.camera.margin;
+    }
+    let materials = match materials {
+        Some(materials) => materials,
+        None => return,
+    };
+    let colors = &config.colors;
+    let mut recolor = |handle: &Handle<ColorMaterial>, rgba: [f32; 4]| {
+        if let Some(material) = color_materials.get_mut(handle) {
+            material.color = ColorConfig::color(rgba);
+        }
+    };
+    for (player, handle) in materials.player_materials.iter().enumerate() {
+        recolor(handle, colors.players[player % colors.players.len()]);
+    }
+    recolor(&materials.monster_materials, colors.monster);
+    recolor(&materials.wall_materials, colors.wall);
+    recolor(&materials.stop_wall_materials, colors.stop_wall);
+    recolor(&materials.reflect_wall_materials, colors.reflect_wall);
+    recolor(
+        &materials.destructible_wall_materials,
+        colors.destructible_wall,
+    );
+    recolor(&materials.door_materials, colors.door);
+    recolor(&materials.pickup_materials, colors.pickup);
+    recolor(&materials.trigger_materials, colors.trigger);
+    recolor(&materials.exit_materials, colors.exit);
+    recolor(&materials.ray_materials, colors.ray);
+    recolor(&materials.projectile_materials, colors.projectile);
+    recolor(&materials.editor_cursor_materials, colors.editor_cursor);
+    recolor(&materials.screen_materials, colors.screen);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_shipped_config_is_the_default() {
+        let text = include_str!("../../assets/config/game.toml");
+        assert_eq!(GlobalConfig::parse(text).unwrap(), GlobalConfig::default());
+    }
+
+    #[test]
+    fn keys_are_optional_but_known() {
+        let config =
+            GlobalConfig::parse("player_speed = 500.0\n[colors]\nwall = [1.0, 0.0, 0.0, 1.0]")
+                .unwrap();
+        assert_eq!(config.player_speed, 500.);
+        assert_eq!(config.colors.wall, [1., 0., 0., 1.]);
+        assert_eq!(config.window_title, GlobalConfig::default().window_title);
+        assert!(GlobalConfig::parse("player_sped = 500.0").is_err());
+    }
+
+    fn is_invalid(change: impl Fn(&mut GlobalConfig)) -> bool {
+        let mut config = GlobalConfig::default();
+        change(&mut config);
+        config.validate().is_err()
+    }
+
+    #[test]
+    fn validate_rejects_bad_values() {
+        assert!(!is_invalid(|_| {}));
+        assert!(is_invalid(|config| config.window_title = STR("  ")));
+        assert!(is_invalid(|config| config.player_speed = 0.));
+        assert!(is_invalid(|config| config.window_width = -600.));
+        assert!(is_invalid(|config| config.player_speed = f32::NAN));
+        assert!(is_invalid(|config| config.player_hitbox_size = Vec2::ZERO));
+        assert!(is_invalid(|config| config.monster_speed.stalker = -1.));
+        assert!(is_invalid(|config| config.colors.players.clear()));
+        assert!(is_invalid(|config| config.colors.door = [0.8, 0.6, 1.5, 1.]));
+        assert!(is_invalid(|config| config.dash.duration = 0.));
+        assert!(is_invalid(|config| config.dash.cooldown = -0.5));
+        assert!(is_invalid(
+            |config| config.camera.deadzone = Vec2::new(-1., 40.)
+        ));
+        assert!(is_invalid(|config| config.camera.smoothing = f32::NAN));
+        assert!(is_invalid(|config| config.generated_maps.size = (48, 3)));
+        assert!(is_invalid(
+            |config| config.generated_maps.block_size = Vec2::ZERO
+        ));
+        assert!(is_invalid(
+            |config| config.generated_maps.topleft.x = f32::INFINITY
+        ));
+        // no deadzone and no smoothing are fine
+        assert!(!is_invalid(|config| {
+            config.camera.deadzone = Vec2::ZERO;
+            config.camera.smoothing = 0.;
+        }));
+        assert!(is_invalid(
+            |config| config.colors.players[1] = [-0.1, 0., 0., 1.]
+        ));
+    }
+}
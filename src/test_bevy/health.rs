@@ -0,0 +1,187 @@
+use std::collections::HashSet;
+
+use bevy::input::*;
+use bevy::prelude::*;
+use bevy::window::*;
+
+use crate::test_bevy::*;
+
+pub struct HealthPlugin;
+
+impl Plugin for HealthPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_event::<DamageEvent>()
+            .add_event::<DeathEvent>()
+            .insert_resource(Score::default())
+            .add_system_set(
+                SystemSet::on_enter(AppState::Playing).with_system(score_reset.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .with_system(
+                        contact_damage
+                            .system()
+                            .label("contact_damage_system")
+                            .after("player_update_hitbox_system")
+                            .after("monster_update_hitbox_system"),
+                    )
+                    .with_system(
+                        health_damage
+                            .system()
+                            .label("health_damage_system")
+                            .after("contact_damage_system")
+                            .after("player_fire_system")
+                            .after("projectile_move_system"),
+                    )
+                    .with_system(
+                        health_death
+                            .system()
+                            .label("health_death_system")
+                            .after("health_damage_system"),
+                    ),
+            );
+    }
+}
+
+impl HealthCmp {
+    // iframes: seconds the entity can't be hurt again after a hit
+    pub fn new(max: f32, iframes: f32) -> Self {
+        Self {
+            current: max,
+            max,
+            iframes,
+        }
+    }
+
+    pub fn current(&self) -> f32 {
+        self.current
+    }
+
+    pub fn max(&self) -> f32 {
+        self.max
+    }
+
+    pub fn ratio(&self) -> f32 {
+        self.current / self.max
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current <= 0.
+    }
+
+    pub fn heal(&mut self, amount: f32) {
+        self.current = (self.current + amount).min(self.max);
+    }
+}
+
+// Hurts whatever player it touches, e.g. a monster running into the player
+pub struct ContactDamageCmp {
+    pub amount: f32,
+}
+
+// Anything that hurts goes through here, so i-frames and death are handled in one place
+pub struct DamageEvent {
+    pub target: Entity,
+    pub amount: f32,
+}
+
+pub struct DeathEvent {
+    pub entity: Entity,
+    pub name: String,
+}
+
+// Points of the current game, monsters are worth their bounty. Only reset when the next game
+// starts, so the game over screen can still show it.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Score {
+    pub points: u32,
+}
+
+fn score_reset(mut score: ResMut<Score>) {
+    *score = Score::default();
+}
+
+fn contact_damage(
+    player_query: Query<(Entity, &RectangleHitboxCmp), With<PlayerEntity>>,
+    attacker_query: Query<(&ContactDamageCmp, &RectangleHitboxCmp), Without<PlayerEntity>>,
+    mut damage_events: EventWriter<DamageEvent>,
+) {
+    for (player, player_hitbox) in player_query.iter() {
+        for (contact_damage, attacker_hitbox) in attacker_query.iter() {
+            if attacker_hitbox.rect.is_rect_overlap(&player_hitbox.rect) {
+                damage_events.send(DamageEvent {
+                    target: player,
+                    amount: contact_damage.amount,
+                });
+            }
+        }
+    }
+}
+
+fn health_damage(
+    mut commands: Commands,
+    mut damage_events: EventReader<DamageEvent>,
+    mut health_query: Query<(&mut HealthCmp, Option<&InvulnerableCmp>)>,
+) {
+    // the i-frames only show up once the commands run, until then this keeps a second hit of
+    // the same frame out
+    let mut hit = HashSet::new();
+    for event in damage_events.iter() {
+        if let Ok((mut health, invulnerable)) = health_query.get_mut(event.target) {
+            if invulnerable.is_some() || hit.contains(&event.target) || health.is_dead() {
+                continue;
+            }
+            health.current -= event.amount;
+            if health.iframes > 0. {
+                hit.insert(event.target);
+                commands
+                    .entity(event.target)
+                    .insert(InvulnerableCmp::new(health.iframes));
+            }
+        }
+    }
+}
+
+// Monsters, destructible walls and players go away, the last player's death is the end of the
+// game
+fn health_death(
+    mut commands: Commands,
+    mut state: ResMut<State<AppState>>,
+    mut score: ResMut<Score>,
+    mut death_events: EventWriter<DeathEvent>,
+    health_query: Query<
+        (
+            Entity,
+            &HealthCmp,
+            Option<&NameCmp>,
+            Option<&PlayerEntity>,
+            Option<&MonsterArchetype>,
+        ),
+        Changed<HealthCmp>,
+    >,
+    player_query: Query<&HealthCmp, With<PlayerEntity>>,
+) {
+    let players_alive = player_query
+        .iter()
+        .filter(|player_health| !player_health.is_dead())
+        .count();
+    let mut player_died = false;
+    for (entity, health, name, player, archetype) in health_query.iter() {
+        if !health.is_dead() {
+            continue;
+        }
+        let name = name.map_or(STR("Something"), |name| name.0.clone());
+        println!("{} died", name);
+        player_died |= player.is_some();
+        if let Some(archetype) = archetype {
+            score.points += archetype.bounty();
+        }
+        commands.entity(entity).despawn_recursive();
+        death_events.send(DeathEvent { entity, name });
+    }
+    if player_died && players_alive == 0 {
+        println!("Game over, score {}", score.points);
+        // over anything else queued in the same tick, e.g. a pause
+        state.overwrite_set(AppState::GameOver).unwrap();
+    }
+}
@@ -33,6 +33,22 @@ pub fn botleft_to_toplef_origin(
     collision::Point::new((from.x(), win_size.h - from.y()))
 }
 
+// The world uses the top-left origin with y going down, independent of the window. Bevy's world
+// space only flips y, what ends up on screen is up to the camera.
+pub fn world_to_bevy(from: &collision::Point) -> collision::Point {
+    collision::Point::new((from.x(), -from.y()))
+}
+
+pub fn bevy_to_world(from: &collision::Point) -> collision::Point {
+    collision::Point::new((from.x(), -from.y()))
+}
+
+// One fixed tick as a Duration. Gameplay timers go by this instead of the frame time, so a
+// replay plays out the same at any frame rate.
+pub fn tick_duration() -> std::time::Duration {
+    std::time::Duration::from_secs_f32(TIME_STEP)
+}
+
 pub fn set_bevy_color_rgba(color: &mut Color, r: f32, g: f32, b: f32, a: f32) {
     color.set_r(r);
     color.set_g(g);
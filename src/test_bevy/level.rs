@@ -0,0 +1,222 @@
+use rand::RngCore;
+
+use bevy::input::*;
+use bevy::prelude::*;
+use bevy::window::*;
+
+use crate::test_bevy::*;
+
+pub struct LevelPlugin;
+
+impl Plugin for LevelPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(LevelManager::from_args())
+            .add_system_set(
+                SystemSet::on_enter(AppState::Playing).with_system(level_start.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .with_system(
+                        level_exit
+                            .system()
+                            .label("level_exit_system")
+                            .before("map_spawn_system"),
+                    )
+                    .with_system(
+                        level_place_player
+                            .system()
+                            .label("level_place_player_system")
+                            .after("map_spawn_system"),
+                    ),
+            )
+            .add_system_set(
+                SystemSet::on_exit(AppState::Playing).with_system(level_teardown.system()),
+            );
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum LevelSource {
+    // path of a .map (or Tiled) file in the assets
+    Asset(String),
+    // a fresh map from the generator, seeded from the GameRng
+    Generated(MapGenerator),
+}
+
+// The levels in the order they are played. Reaching an exit trigger tears down the monsters of
+// the level and swaps the current map, map_spawn then replaces the tiles. The player entity is
+// kept (and with it everything it carries), it only gets moved to the new spawn point.
+pub struct LevelManager {
+    pub levels: Vec<LevelSource>,
+    // once the list runs out the last level is played again and again, only makes sense for
+    // generated ones
+    pub endless: bool,
+    pub current: usize,
+    // the player goes to the spawn point of the next map that comes out
+    place_player: bool,
+}
+
+impl LevelManager {
+    pub fn new(levels: Vec<LevelSource>, endless: bool) -> Self {
+        Self {
+            levels,
+            endless,
+            current: 0,
+            place_player: false,
+        }
+    }
+
+    pub fn campaign() -> Self {
+        Self::new(
+            vec![
+                LevelSource::Asset(STR("maps/arena.map")),
+                LevelSource::Asset(STR("maps/vault.map")),
+            ],
+            false,
+        )
+    }
+
+    // endless generated levels with --generate <bsp|caves|drunkard>, the campaign otherwise
+    pub fn from_args() -> Self {
+        match MapGenerator::from_args() {
+            Some(generator) => Self::new(vec![LevelSource::Generated(generator)], true),
+            None => Self::campaign(),
+        }
+    }
+
+    // e.g. the level of a saved game, the map gets swapped separately
+    pub fn set_current(&mut self, current: usize) {
+        self.current = current;
+        self.place_player = false;
+    }
+
+    pub fn level(&self, idx: usize) -> Option<&LevelSource> {
+        match self.levels.get(idx) {
+            Some(source) => Some(source),
+            None if self.endless => self.levels.last(),
+            None => None,
+        }
+    }
+}
+
+fn load_level(
+    source: &LevelSource,
+    asset_server: &AssetServer,
+    maps: &mut Assets<Map>,
+    rng: &mut GameRng,
+    config: &GlobalConfig,
+) -> Result<Handle<Map>, MapError> {
+    match source {
+        LevelSource::Asset(path) => Ok(asset_server.load(path.as_str())),
+        LevelSource::Generated(generator) => {
+            let generated = &config.generated_maps;
+            // the passages fit the biggest hitbox
+            let hitbox = config.player_hitbox_size.max(config.monster_hitbox_size);
+            let map = generate_map(&MapGenSettings {
+                generator: *generator,
+                seed: rng.stream("map_generation").next_u64(),
+                size: generated.size,
+                monster_spawns: 4,
+                footprint: footprint_cells(hitbox, generated.block_size),
+                block_size: generated.block_size,
+                topleft: generated.topleft,
+            })?;
+            Ok(maps.add(map))
+        }
+    }
+}
+
+fn level_start(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut maps: ResMut<Assets<Map>>,
+    mut rng: ResMut<GameRng>,
+    config: Res<GlobalConfig>,
+    level: Res<LevelManager>,
+) {
+    if let Some(source) = level.level(level.current) {
+        match load_level(source, &asset_server, &mut maps, &mut rng, &config) {
+            Ok(handle) => commands.insert_resource(CurrentMap(handle)),
+            Err(err) => println!("Can't load level {}: {}", level.current + 1, err),
+        }
+    }
+}
+
+fn level_exit(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut maps: ResMut<Assets<Map>>,
+    mut rng: ResMut<GameRng>,
+    config: Res<GlobalConfig>,
+    mut level: ResMut<LevelManager>,
+    mut current_map: ResMut<CurrentMap>,
+    mut spawner: ResMut<WaveSpawner>,
+    mut trigger_events: EventReader<TriggerEnteredEvent>,
+    monster_query: Query<Entity, With<MonsterEntity>>,
+) {
+    if !trigger_events
+        .iter()
+        .any(|event| event.name == EXIT_TRIGGER_NAME)
+    {
+        return;
+    }
+    let next = level.current + 1;
+    let source = match level.level(next) {
+        Some(source) => source.clone(),
+        None => {
+            println!("That was the last level, well done!");
+            return;
+        }
+    };
+
+    // the player stays on the current level if the next one can't be made
+    let handle = match load_level(&source, &asset_server, &mut maps, &mut rng, &config) {
+        Ok(handle) => handle,
+        Err(err) => {
+            println!("Can't load level {}: {}", next + 1, err);
+            return;
+        }
+    };
+
+    for monster in monster_query.iter() {
+        commands.entity(monster).despawn();
+    }
+    *spawner = WaveSpawner::survival();
+    current_map.0 = handle;
+    level.current = next;
+    level.place_player = true;
+    println!("Level {}", next + 1);
+}
+
+fn level_place_player(
+    mut level: ResMut<LevelManager>,
+    mut map_spawned_events: EventReader<MapSpawnedEvent>,
+    mut player_query: Query<
+        (
+            &PlayerIdCmp,
+            &mut MovementCmp,
+            &mut RectangleHitboxCmp,
+            &mut Transform,
+        ),
+        With<PlayerEntity>,
+    >,
+) {
+    for event in map_spawned_events.iter() {
+        if !level.place_player {
+            continue;
+        }
+        for (player_id, mut player_mov, mut player_hitbox, mut player_tf) in player_query.iter_mut()
+        {
+            if let Some(sp) = player_spawn_point(&event.player_spawns, player_id.0) {
+                place_player(sp, &mut player_mov, &mut player_hitbox, &mut player_tf);
+            }
+        }
+        level.place_player = false;
+    }
+}
+
+// the next game starts over from the first level
+fn level_teardown(mut level: ResMut<LevelManager>) {
+    level.current = 0;
+    level.place_player = false;
+}
@@ -0,0 +1,222 @@
+use serde::{Deserialize, Serialize};
+
+use bevy::input::*;
+use bevy::prelude::*;
+use bevy::window::*;
+
+use crate::test_bevy::*;
+
+pub struct LocomotionPlugin;
+
+impl Plugin for LocomotionPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_set(
+            SystemSet::on_update(AppState::Playing)
+                .with_system(
+                    locomotion
+                        .system()
+                        .label("locomotion_system")
+                        .after("player_movement_system")
+                        .before("player_collision_system"),
+                )
+                .with_system(invulnerable_tick.system()),
+        );
+    }
+}
+
+// Velocity that builds up and dies down instead of jumping to full speed. Whoever drives the
+// entity sets `input` (length up to 1) and locomotion turns it into MovementCmp::direction.
+pub struct LocomotionCmp {
+    pub max_speed: f32,
+    // px/s² while there is input
+    pub acceleration: f32,
+    // px/s² while there is none
+    pub friction: f32,
+    pub input: Vec2,
+    velocity: Vec2,
+}
+
+impl LocomotionCmp {
+    pub fn new(max_speed: f32, acceleration: f32, friction: f32) -> Self {
+        Self {
+            max_speed,
+            acceleration,
+            friction,
+            input: Vec2::ZERO,
+            velocity: Vec2::ZERO,
+        }
+    }
+
+    pub fn player(config: &GlobalConfig) -> Self {
+        Self::new(
+            config.player_speed,
+            config.player_acceleration,
+            config.player_friction,
+        )
+    }
+
+    pub fn velocity(&self) -> Vec2 {
+        self.velocity
+    }
+
+    pub fn set_velocity(&mut self, velocity: Vec2) {
+        self.velocity = velocity;
+    }
+}
+
+// A short burst of speed in the input (or current) direction, invulnerable for a moment
+pub struct DashCmp {
+    pub speed: f32,
+    // seconds
+    pub duration: f32,
+    pub cooldown: f32,
+    pub iframes: f32,
+    // set by the driver, consumed by locomotion
+    pub requested: bool,
+    direction: Vec2,
+    time_left: f32,
+    cooldown_left: f32,
+}
+
+impl DashCmp {
+    pub fn new(speed: f32, duration: f32, cooldown: f32, iframes: f32) -> Self {
+        Self {
+            speed,
+            duration,
+            cooldown,
+            iframes,
+            requested: false,
+            direction: Vec2::ZERO,
+            time_left: 0.,
+            cooldown_left: 0.,
+        }
+    }
+
+    pub fn player(config: &GlobalConfig) -> Self {
+        let dash = &config.dash;
+        Self::new(dash.speed, dash.duration, dash.cooldown, dash.iframes)
+    }
+
+    pub fn is_dashing(&self) -> bool {
+        self.time_left > 0.
+    }
+
+    // How far the dash and its cooldown got, the tuning comes from the config
+    pub fn snapshot(&self) -> DashSnapshot {
+        DashSnapshot {
+            direction: self.direction,
+            time_left: self.time_left,
+            cooldown_left: self.cooldown_left,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &DashSnapshot) {
+        self.direction = snapshot.direction;
+        self.time_left = snapshot.time_left;
+        self.cooldown_left = snapshot.cooldown_left;
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DashSnapshot {
+    pub direction: Vec2,
+    pub time_left: f32,
+    pub cooldown_left: f32,
+}
+
+// Nothing can hurt the entity until the timer runs out
+pub struct InvulnerableCmp {
+    pub timer: Timer,
+}
+
+impl InvulnerableCmp {
+    pub fn new(seconds: f32) -> Self {
+        Self {
+            timer: Timer::from_seconds(seconds, false),
+        }
+    }
+}
+
+// Moves velocity towards target by at most max_delta
+pub fn approach_velocity(velocity: Vec2, target: Vec2, max_delta: f32) -> Vec2 {
+    velocity + (target - velocity).clamp_length_max(max_delta)
+}
+
+fn locomotion(
+    mut commands: Commands,
+    mut query: Query<(
+        Entity,
+        &mut LocomotionCmp,
+        &mut MovementCmp,
+        Option<&mut DashCmp>,
+    )>,
+) {
+    for (entity, mut locomotion, mut mov, mut dash) in query.iter_mut() {
+        // start from what the entity really did last frame, so pushing into a wall doesn't keep
+        // a velocity the collision took away
+        locomotion.velocity = mov.delta / TIME_STEP;
+        // diagonals are no faster than straight lines
+        let input = locomotion.input.clamp_length_max(1.);
+
+        let dashing = match dash.as_deref_mut() {
+            Some(dash) => {
+                dash.cooldown_left = (dash.cooldown_left - TIME_STEP).max(0.);
+                dash.time_left = (dash.time_left - TIME_STEP).max(0.);
+                let facing = if input != Vec2::ZERO {
+                    input
+                } else {
+                    locomotion.velocity
+                };
+                if dash.requested && dash.cooldown_left <= 0. && facing != Vec2::ZERO {
+                    dash.direction = facing.normalize();
+                    dash.time_left = dash.duration;
+                    dash.cooldown_left = dash.cooldown;
+                    commands
+                        .entity(entity)
+                        .insert(InvulnerableCmp::new(dash.iframes));
+                }
+                dash.requested = false;
+                if dash.is_dashing() {
+                    locomotion.velocity = dash.direction * dash.speed;
+                }
+                dash.is_dashing()
+            }
+            None => false,
+        };
+
+        if !dashing {
+            let rate = if input != Vec2::ZERO {
+                locomotion.acceleration
+            } else {
+                locomotion.friction
+            };
+            locomotion.velocity = approach_velocity(
+                locomotion.velocity,
+                input * locomotion.max_speed,
+                rate * TIME_STEP,
+            );
+        }
+
+        // MovementCmp moves by direction * speed, so the direction carries the velocity
+        mov.speed = Vec2::new(locomotion.max_speed, locomotion.max_speed);
+        mov.direction = locomotion.velocity / locomotion.max_speed;
+    }
+}
+
+fn invulnerable_tick(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut InvulnerableCmp, Option<&mut Visible>)>,
+) {
+    for (entity, mut invulnerable, visible) in query.iter_mut() {
+        invulnerable.timer.tick(tick_duration());
+        let finished = invulnerable.timer.finished();
+        // blink while it lasts
+        if let Some(mut visible) = visible {
+            visible.is_visible =
+                finished || ((invulnerable.timer.elapsed_secs() * 20.) as u32).is_multiple_of(2);
+        }
+        if finished {
+            commands.entity(entity).remove::<InvulnerableCmp>();
+        }
+    }
+}
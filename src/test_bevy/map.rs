@@ -1,48 +1,689 @@
 use ascii::AsciiString;
 use fstrings::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 
+use bevy::asset::{AssetLoader, LoadContext, LoadedAsset};
 use bevy::input::*;
 use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::utils::BoxedFuture;
 use bevy::window::*;
 
 use crate::test_bevy::*;
 
+// A level as stored in assets/maps/*.map: a small "key: value" header, a "---" line, then the
+// ASCII grid. For example:
+//
+// name: Arena
+// size: 20 20
+// block_size: 20 20
+// topleft: 40 40
+// legend: & = ReflectWall
+// ---
+// ********************
+// *  M            M  *
+// ...
+#[derive(Debug, PartialEq, TypeUuid)]
+#[uuid = "2b9e7c44-1f0a-4d6e-8c3b-7a5d9e210f48"]
 pub struct Map {
+    name: String,
     map_size: Vec2,
     block_size: Vec2,
     topleft: Vec2,
+    legend: MapLegend,
     map_string: AsciiString,
+    // trigger zones that don't follow the grid (from imported maps)
+    triggers: Vec<MapTrigger>,
 }
 
-pub fn get_map() -> Map {
-    Map {
-        map_size: Vec2::new(20., 20.),
-        block_size: Vec2::new(20., 20.),
-        topleft: Vec2::new(20., 20.),
-        map_string: AsciiString::from_ascii(
-            "
-********************
-*                  *
-*                  *
-*                  *
-*                  *
-*                  *
-*                  *
-*                  *
-*                  *
-*                  *
-*                  *
-*                  *
-*                  *
-*                  *
-*                  *
-*                  *
-*                  *
-*                  *
-*                  *
-********************",
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MapTrigger {
+    pub name: String,
+    // top-left corner relative to the map's topleft
+    pub offset: Vec2,
+    pub size: Vec2,
+}
+
+// A block of same-kind wall cells merged into one collider, in cells
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MapWallRect {
+    pub kind: TileKind,
+    pub col: usize,
+    pub row: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Map {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn block_size(&self) -> Vec2 {
+        self.block_size
+    }
+
+    pub fn topleft(&self) -> Vec2 {
+        self.topleft
+    }
+
+    // (columns, rows) of the grid
+    pub fn map_size(&self) -> (usize, usize) {
+        (self.map_size.x as usize, self.map_size.y as usize)
+    }
+
+    // size of the whole grid in pixels
+    pub fn world_size(&self) -> Vec2 {
+        self.map_size * self.block_size
+    }
+
+    pub fn legend(&self) -> &MapLegend {
+        &self.legend
+    }
+
+    pub fn grid(&self) -> Vec<Vec<char>> {
+        self.map_string
+            .lines()
+            .map(|line| line.chars().map(|char| char.as_char()).collect())
+            .collect()
+    }
+
+    // The (col, row) of the cell under a point in top-left window coordinates
+    pub fn cell_at(&self, point: Vec2) -> Option<(usize, usize)> {
+        let cell = (point - self.topleft) / self.block_size;
+        if cell.x < 0. || cell.y < 0. || cell.x >= self.map_size.x || cell.y >= self.map_size.y {
+            return None;
+        }
+        Some((cell.x as usize, cell.y as usize))
+    }
+
+    pub fn cell_topleft(&self, col: usize, row: usize) -> Vec2 {
+        self.topleft + Vec2::new(col as f32, row as f32) * self.block_size
+    }
+
+    // The same map with another grid of the same size. Not validated, so the editor can show
+    // half-finished maps; parse the output of map_to_string to check one.
+    pub fn with_grid(&self, grid: &[Vec<char>]) -> Map {
+        let rows: Vec<String> = grid.iter().map(|row| row.iter().collect()).collect();
+        Map {
+            name: self.name.clone(),
+            map_size: self.map_size,
+            block_size: self.block_size,
+            topleft: self.topleft,
+            legend: self.legend.clone(),
+            map_string: AsciiString::from_ascii(rows.join("\n")).unwrap_or_default(),
+            triggers: self.triggers.clone(),
+        }
+    }
+
+    pub fn triggers(&self) -> &[MapTrigger] {
+        &self.triggers
+    }
+
+    // map_to_string leaves the trigger zones out, this puts them back
+    pub fn with_triggers(mut self, triggers: Vec<MapTrigger>) -> Map {
+        self.triggers = triggers;
+        self
+    }
+
+    // the tile kind of every cell, None for glyphs missing from the legend
+    pub fn tile_grid(&self) -> Vec<Vec<Option<TileKind>>> {
+        self.map_string
+            .lines()
+            .map(|line| {
+                line.chars()
+                    .map(|char| self.legend.get(char.as_char()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    // Spawn points for count players: the 'P' cell for the first one, then for each of the others
+    // the free cell closest to it, so nobody spawns inside someone else. Free means a footprint x
+    // footprint entity fits without touching a wall, the exit or another spawn, and closest is
+    // counted in steps around the walls. With no room left players share the 'P' cell.
+    pub fn player_spawns(&self, count: usize, footprint: usize) -> Vec<Vec2> {
+        let tiles = self.tile_grid();
+        let mut start = None;
+        let mut passable = Vec::new();
+        for (row, tile_row) in tiles.iter().enumerate() {
+            passable.push(Vec::new());
+            for (col, tile) in tile_row.iter().enumerate() {
+                if *tile == Some(TileKind::PlayerSpawn) {
+                    start = Some((col, row));
+                }
+                passable[row].push(
+                    !matches!(
+                        tile,
+                        Some(TileKind::Door | TileKind::DestructibleWall | TileKind::Exit)
+                    ) && !tile.is_some_and(TileKind::is_static_wall),
+                );
+            }
+        }
+        let start = match start {
+            Some(start) => start,
+            None => return Vec::new(),
+        };
+        let mut spawns = vec![start];
+        let mut taken: Vec<Vec<bool>> = passable
+            .iter()
+            .map(|row| row.iter().map(|passable| !passable).collect())
+            .collect();
+        let take = |taken: &mut Vec<Vec<bool>>, (col, row): (usize, usize)| {
+            for taken_row in taken.iter_mut().skip(row).take(footprint) {
+                for cell in taken_row.iter_mut().skip(col).take(footprint) {
+                    *cell = true;
+                }
+            }
+        };
+        take(&mut taken, start);
+        for _ in 1..count {
+            let free = clearance_grid(&taken, footprint);
+            // breadth first from the 'P' cell, the first free cell is the closest
+            let mut seen = vec![vec![false; passable[0].len()]; passable.len()];
+            let mut queue = std::collections::VecDeque::new();
+            seen[start.1][start.0] = true;
+            queue.push_back(start);
+            let mut found = None;
+            while let Some((col, row)) = queue.pop_front() {
+                if free[row][col] {
+                    found = Some((col, row));
+                    break;
+                }
+                let neighbors = [
+                    (col.wrapping_sub(1), row),
+                    (col + 1, row),
+                    (col, row.wrapping_sub(1)),
+                    (col, row + 1),
+                ];
+                for (next_col, next_row) in neighbors.iter().copied() {
+                    let is_passable = passable
+                        .get(next_row)
+                        .and_then(|passable_row| passable_row.get(next_col))
+                        .copied()
+                        .unwrap_or(false);
+                    if is_passable && !seen[next_row][next_col] {
+                        seen[next_row][next_col] = true;
+                        queue.push_back((next_col, next_row));
+                    }
+                }
+            }
+            match found {
+                Some(cell) => {
+                    take(&mut taken, cell);
+                    spawns.push(cell);
+                }
+                None => break,
+            }
+        }
+        spawns
+            .iter()
+            .map(|(col, row)| self.cell_topleft(*col, *row))
+            .collect()
+    }
+
+    // Greedy meshing of the static walls: each wall cell not covered yet grows right as far as
+    // the row allows, then down while the whole span below is the same kind of wall. The result
+    // is a handful of rectangles instead of one collider per cell, so there are no seams between
+    // neighboring blocks for the hitboxes to snag on.
+    pub fn wall_rects(&self) -> Vec<MapWallRect> {
+        let grid = self.tile_grid();
+        let height = grid.len();
+        let width = grid.first().map_or(0, |row| row.len());
+        let mut covered = vec![vec![false; width]; height];
+        let mut rects = Vec::new();
+
+        for row in 0..height {
+            for col in 0..width {
+                let kind = match grid[row][col] {
+                    Some(kind) if kind.is_static_wall() && !covered[row][col] => kind,
+                    _ => continue,
+                };
+                let mergeable = |r: usize, c: usize| grid[r][c] == Some(kind) && !covered[r][c];
+
+                let mut rect_width = 1;
+                while col + rect_width < width && mergeable(row, col + rect_width) {
+                    rect_width += 1;
+                }
+                let mut rect_height = 1;
+                while row + rect_height < height
+                    && (col..col + rect_width).all(|c| mergeable(row + rect_height, c))
+                {
+                    rect_height += 1;
+                }
+
+                for covered_row in covered.iter_mut().skip(row).take(rect_height) {
+                    for cell in covered_row.iter_mut().skip(col).take(rect_width) {
+                        *cell = true;
+                    }
+                }
+                rects.push(MapWallRect {
+                    kind,
+                    col,
+                    row,
+                    width: rect_width,
+                    height: rect_height,
+                });
+            }
+        }
+        rects
+    }
+}
+
+// Top-left corners of the 'M' cells, where the wave spawner releases monsters
+pub struct MonsterSpawnPoints(pub Vec<Vec2>);
+
+// Top-left corners of the player spawns, one per local player, see Map::player_spawns
+pub struct PlayerSpawnPoints(pub Vec<Vec2>);
+
+// The level being played, its walls are respawned whenever the asset (re)loads or the handle
+// is swapped for another map
+pub struct CurrentMap(pub Handle<Map>);
+
+// Sent once the tiles of the current map are out. The spawn point resources are only updated at
+// the end of the stage, so the player spawns come with the event.
+pub struct MapSpawnedEvent {
+    pub name: String,
+    pub player_spawns: Vec<Vec2>,
+}
+
+const MAP_SEPARATOR: &str = "---";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MapErrorKind {
+    MissingSeparator,
+    BadHeaderLine(String),
+    UnknownHeaderKey(String),
+    BadNumbers {
+        key: String,
+        value: String,
+    },
+    MissingHeaderKey(&'static str),
+    BadLegend(String),
+    EmptyGrid,
+    NonAscii(char),
+    RaggedRow {
+        expected: usize,
+        found: usize,
+    },
+    UnknownGlyph(char),
+    MissingPlayerSpawn,
+    DuplicatePlayerSpawn {
+        first_line: usize,
+        first_column: usize,
+    },
+    OpenBorder(char),
+    SizeMismatch {
+        declared: (usize, usize),
+        actual: (usize, usize),
+    },
+    // Tiled maps that store their tiles in chunks
+    TiledInfinite,
+    // a Tiled tile layer that isn't plain CSV, e.g. "base64 zlib"
+    TiledEncoding {
+        layer: String,
+        encoding: String,
+    },
+}
+
+// Where (1-based line and column of the map file) and why a map failed to parse
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: MapErrorKind,
+}
+
+impl MapError {
+    fn new(line: usize, column: usize, kind: MapErrorKind) -> Self {
+        Self { line, column, kind }
+    }
+}
+
+impl fmt::Display for MapErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapErrorKind::MissingSeparator => write!(
+                f,
+                "no '{}' line between the header and the grid",
+                MAP_SEPARATOR
+            ),
+            MapErrorKind::BadHeaderLine(line) => {
+                write!(f, "header line '{}' is not 'key: value'", line)
+            }
+            MapErrorKind::UnknownHeaderKey(key) => write!(f, "unknown header key '{}'", key),
+            MapErrorKind::BadNumbers { key, value } => {
+                write!(f, "'{}' expects two numbers, got '{}'", key, value)
+            }
+            MapErrorKind::MissingHeaderKey(key) => write!(f, "header is missing '{}'", key),
+            MapErrorKind::BadLegend(legend) => write!(
+                f,
+                "legend '{}' is not 'glyph = TileKind' with a single character glyph",
+                legend
+            ),
+            MapErrorKind::EmptyGrid => write!(f, "the grid is empty"),
+            MapErrorKind::NonAscii(glyph) => write!(f, "non-ASCII character '{}'", glyph),
+            MapErrorKind::RaggedRow { expected, found } => write!(
+                f,
+                "row is {} columns wide but the first row is {}",
+                found, expected
+            ),
+            MapErrorKind::UnknownGlyph(glyph) => {
+                write!(f, "'{}' is not in the legend", glyph.escape_default())
+            }
+            MapErrorKind::MissingPlayerSpawn => write!(f, "the grid has no player spawn"),
+            MapErrorKind::DuplicatePlayerSpawn {
+                first_line,
+                first_column,
+            } => write!(
+                f,
+                "second player spawn, the first one is at line {}, column {}",
+                first_line, first_column
+            ),
+            MapErrorKind::OpenBorder(glyph) => write!(
+                f,
+                "the border has to be walls but '{}' leaves it open",
+                glyph
+            ),
+            MapErrorKind::SizeMismatch { declared, actual } => write!(
+                f,
+                "header says the map is {}x{} but the grid is {}x{}",
+                declared.0, declared.1, actual.0, actual.1
+            ),
+            MapErrorKind::TiledInfinite => write!(
+                f,
+                "infinite Tiled maps are not supported, untick Infinite in the map properties"
+            ),
+            MapErrorKind::TiledEncoding { layer, encoding } => write!(
+                f,
+                "tile layer '{}' is stored as {}, set the Tile Layer Format to CSV",
+                layer, encoding
+            ),
+        }
+    }
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.kind
         )
-        .unwrap(),
+    }
+}
+
+impl std::error::Error for MapError {}
+
+// the Tiled import fails with the bare kind, a JSON file has no grid lines to point at
+impl std::error::Error for MapErrorKind {}
+
+// Writes the map back in the .map format. Trigger zones imported from Tiled have no glyph and
+// are left out.
+pub fn map_to_string(map: &Map) -> String {
+    let mut text = String::new();
+    text += &format!("name: {}\n", map.name);
+    text += &format!("size: {} {}\n", map.map_size.x, map.map_size.y);
+    text += &format!("block_size: {} {}\n", map.block_size.x, map.block_size.y);
+    text += &format!("topleft: {} {}\n", map.topleft.x, map.topleft.y);
+    // only the glyphs that differ from the default legend
+    let default_legend = MapLegend::default();
+    let mut glyphs: Vec<&char> = map.legend.0.keys().collect();
+    glyphs.sort();
+    for glyph in glyphs {
+        let kind = map.legend.0[glyph];
+        if default_legend.get(*glyph) != Some(kind) {
+            text += &format!("legend: {} = {}\n", glyph, kind.name());
+        }
+    }
+    text += MAP_SEPARATOR;
+    text += "\n";
+    text += map.map_string.as_str();
+    text += "\n";
+    text
+}
+
+fn parse_map_vec2(value: &str) -> Option<Vec2> {
+    let numbers = value
+        .split_whitespace()
+        .map(|n| n.parse::<f32>())
+        .collect::<Result<Vec<f32>, _>>()
+        .ok()?;
+    match numbers[..] {
+        [x, y] => Some(Vec2::new(x, y)),
+        _ => None,
+    }
+}
+
+fn parse_map_legend(value: &str) -> Option<(char, TileKind)> {
+    let (glyph, kind) = value.split_once('=')?;
+    let mut glyphs = glyph.trim().chars();
+    match (glyphs.next(), glyphs.next()) {
+        (Some(glyph), None) => Some((glyph, TileKind::from_name(kind.trim())?)),
+        _ => None,
+    }
+}
+
+// Tiles a player can't walk out of the map through
+fn is_border_tile(kind: TileKind) -> bool {
+    kind.is_static_wall()
+}
+
+pub fn parse_map(text: &str) -> Result<Map, MapError> {
+    let lines: Vec<&str> = text
+        .lines()
+        .map(|line| line.strip_suffix('\r').unwrap_or(line))
+        .collect();
+    let separator = match lines.iter().position(|line| line.trim() == MAP_SEPARATOR) {
+        Some(idx) => idx,
+        None => {
+            return Err(MapError::new(
+                lines.len() + 1,
+                1,
+                MapErrorKind::MissingSeparator,
+            ))
+        }
+    };
+
+    let mut name = None;
+    let mut map_size = None;
+    let mut block_size = None;
+    let mut topleft = None;
+    let mut legend = MapLegend::default();
+    for (idx, line) in lines[..separator].iter().enumerate() {
+        let line_no = idx + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (key, value) = match line.split_once(':') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => {
+                return Err(MapError::new(
+                    line_no,
+                    1,
+                    MapErrorKind::BadHeaderLine(line.to_string()),
+                ))
+            }
+        };
+        // errors about the value point at where the value starts
+        let value_column = line.find(':').unwrap() + 2;
+        let bad_numbers = || {
+            MapError::new(
+                line_no,
+                value_column,
+                MapErrorKind::BadNumbers {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                },
+            )
+        };
+        match key {
+            "name" => name = Some(value.to_string()),
+            "size" => map_size = Some(parse_map_vec2(value).ok_or_else(bad_numbers)?),
+            "block_size" => block_size = Some(parse_map_vec2(value).ok_or_else(bad_numbers)?),
+            "topleft" => topleft = Some(parse_map_vec2(value).ok_or_else(bad_numbers)?),
+            // can be repeated, one glyph per line
+            "legend" => {
+                let (glyph, kind) = parse_map_legend(value).ok_or_else(|| {
+                    MapError::new(
+                        line_no,
+                        value_column,
+                        MapErrorKind::BadLegend(value.to_string()),
+                    )
+                })?;
+                legend.0.insert(glyph, kind);
+            }
+            _ => {
+                return Err(MapError::new(
+                    line_no,
+                    1,
+                    MapErrorKind::UnknownHeaderKey(key.to_string()),
+                ))
+            }
+        }
+    }
+    let separator_line = separator + 1;
+    let missing = |key| MapError::new(separator_line, 1, MapErrorKind::MissingHeaderKey(key));
+    let map_size = map_size.ok_or_else(|| missing("size"))?;
+    let block_size = block_size.ok_or_else(|| missing("block_size"))?;
+    let topleft = topleft.ok_or_else(|| missing("topleft"))?;
+
+    // the grid starts right after the separator, trailing blank lines are ignored
+    let mut grid_rows = &lines[separator + 1..];
+    while let Some((last, rest)) = grid_rows.split_last() {
+        if !last.trim().is_empty() {
+            break;
+        }
+        grid_rows = rest;
+    }
+    let map = build_map(
+        name.unwrap_or_else(|| STR("Unnamed")),
+        block_size,
+        topleft,
+        legend,
+        grid_rows,
+        separator_line + 1,
+        Vec::new(),
+    )?;
+
+    if map.map_size != map_size {
+        return Err(MapError::new(
+            separator_line,
+            1,
+            MapErrorKind::SizeMismatch {
+                declared: (map_size.x as usize, map_size.y as usize),
+                actual: (map.map_size.x as usize, map.map_size.y as usize),
+            },
+        ));
+    }
+    Ok(map)
+}
+
+// Validates the grid and puts a Map together. Shared by the .map parser and the importers, errors
+// point at first_grid_line + row.
+pub fn build_map(
+    name: String,
+    block_size: Vec2,
+    topleft: Vec2,
+    legend: MapLegend,
+    grid_rows: &[&str],
+    first_grid_line: usize,
+    triggers: Vec<MapTrigger>,
+) -> Result<Map, MapError> {
+    if grid_rows.is_empty() {
+        return Err(MapError::new(first_grid_line, 1, MapErrorKind::EmptyGrid));
+    }
+    let width = grid_rows[0].chars().count();
+    let height = grid_rows.len();
+    let mut player_spawn: Option<(usize, usize)> = None;
+    for (row, grid_row) in grid_rows.iter().enumerate() {
+        let line_no = first_grid_line + row;
+        let found = grid_row.chars().count();
+        if found != width {
+            return Err(MapError::new(
+                line_no,
+                found.min(width) + 1,
+                MapErrorKind::RaggedRow {
+                    expected: width,
+                    found,
+                },
+            ));
+        }
+        for (col, glyph) in grid_row.chars().enumerate() {
+            let column = col + 1;
+            if !glyph.is_ascii() {
+                return Err(MapError::new(
+                    line_no,
+                    column,
+                    MapErrorKind::NonAscii(glyph),
+                ));
+            }
+            let kind = legend
+                .get(glyph)
+                .ok_or_else(|| MapError::new(line_no, column, MapErrorKind::UnknownGlyph(glyph)))?;
+            let on_border = row == 0 || row == height - 1 || col == 0 || col == width - 1;
+            if on_border && !is_border_tile(kind) {
+                return Err(MapError::new(
+                    line_no,
+                    column,
+                    MapErrorKind::OpenBorder(glyph),
+                ));
+            }
+            // the other local players spawn next to it, see Map::player_spawns
+            if kind == TileKind::PlayerSpawn {
+                if let Some((first_line, first_column)) = player_spawn {
+                    return Err(MapError::new(
+                        line_no,
+                        column,
+                        MapErrorKind::DuplicatePlayerSpawn {
+                            first_line,
+                            first_column,
+                        },
+                    ));
+                }
+                player_spawn = Some((line_no, column));
+            }
+        }
+    }
+    if player_spawn.is_none() {
+        return Err(MapError::new(
+            first_grid_line,
+            1,
+            MapErrorKind::MissingPlayerSpawn,
+        ));
+    }
+
+    Ok(Map {
+        name,
+        map_size: Vec2::new(width as f32, height as f32),
+        block_size,
+        topleft,
+        legend,
+        // validated above, every glyph is ASCII
+        map_string: AsciiString::from_ascii(grid_rows.join("\n")).unwrap(),
+        triggers,
+    })
+}
+
+#[derive(Default)]
+pub struct MapLoader;
+
+impl AssetLoader for MapLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let map = parse_map(std::str::from_utf8(bytes)?)?;
+            load_context.set_default_asset(LoadedAsset::new(map));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["map"]
     }
 }
 
@@ -50,48 +691,277 @@ pub struct MapPlugin;
 
 impl Plugin for MapPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_startup_stage("game_setup_walls", SystemStage::single(map_spawn.system()));
+        app.add_asset::<Map>()
+            .init_asset_loader::<MapLoader>()
+            .init_asset_loader::<TiledMapLoader>()
+            .insert_resource(MonsterSpawnPoints(Vec::new()))
+            .insert_resource(PlayerSpawnPoints(Vec::new()))
+            .add_event::<MapSpawnedEvent>()
+            .add_startup_system(map_watch.system())
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .with_system(map_spawn.system().label("map_spawn_system")),
+            )
+            .add_system_set(
+                SystemSet::on_exit(AppState::Playing).with_system(map_teardown.system()),
+            );
     }
 }
 
-fn map_spawn(mut commands: Commands, materials: Res<Materials>, win_size: Res<GameWindowSize>) {
-    let map = get_map();
-
-    let mut wall_spawn = |sp: Vec2, block_size: Vec2| {
-        let (wall_w, wall_h): (f32, f32) = (block_size.x, block_size.y);
-        let mut sp = Point::new(sp);
-        let mut bevy_sp = topleft_to_mid_origin(&sp, &win_size);
-        bevy_sp = bevy_sp + Point::new((wall_w / 2., -wall_h / 2.));
+fn map_watch(asset_server: Res<AssetServer>, playback: Option<Res<ReplayPlayback>>) {
+    // a replay plays on the maps as they were recorded
+    if playback.is_some() {
+        return;
+    }
+    // lets the maps be edited while the game runs
+    if let Err(err) = asset_server.watch_for_changes() {
+        println!("Can't watch the assets for changes: {:?}", err);
+    }
+}
 
-        commands
-            .spawn_bundle(SpriteBundle {
-                material: materials.wall_materials.clone(),
-                sprite: Sprite::new(Vec2::new(wall_w, wall_h)),
-                transform: Transform::from_xyz(bevy_sp.x(), bevy_sp.y(), 1.),
-                ..Default::default()
-            })
-            .insert(WallEntity)
-            .insert(NameCmp(f!("Wall {sp}")))
-            .insert(RectangleHitboxCmp {
-                rect: collision::Rect::new((sp, wall_w, wall_h)),
-            })
-            .insert(CollideCmp {
-                property: CollideType::Stop as u32 | CollideType::Reflect as u32,
-            });
-        println!("Spawned Wall at ({} -> {})", sp, bevy_sp);
+// Spawns the tiles once the current map is loaded and respawns them every time the file changes
+fn map_spawn(
+    mut commands: Commands,
+    materials: Res<Materials>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    config: Res<GlobalConfig>,
+    players: Res<LocalPlayers>,
+    maps: Res<Assets<Map>>,
+    current_map: Res<CurrentMap>,
+    mut map_events: EventReader<AssetEvent<Map>>,
+    mut map_spawned_events: EventWriter<MapSpawnedEvent>,
+    tile_query: Query<Entity, With<MapTileCmp>>,
+    // the handle changed but the map may still be loading
+    mut pending: Local<bool>,
+) {
+    if current_map.is_changed() {
+        *pending = true;
+    }
+    for event in map_events.iter() {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle }
+                if *handle == current_map.0 =>
+            {
+                *pending = true
+            }
+            _ => {}
+        }
+    }
+    let map = match maps.get(&current_map.0) {
+        Some(map) if *pending => map,
+        _ => return,
     };
+    *pending = false;
 
-    let mut row = 0_f32;
-    let mut col = 0_f32;
-    for char in map.map_string.into_iter() {
-        if *char == '\n' {
-            row += 1_f32;
-            col = 0_f32;
-        }
-        if *char == '*' {
-            let sp = map.topleft + Vec2::new(col * map.block_size.x, row * map.block_size.y);
-            wall_spawn(sp, map.block_size);
+    for tile in tile_query.iter() {
+        commands.entity(tile).despawn();
+    }
+    println!("Loading map {}", map.name);
+    let player_spawns = spawn_map(
+        &mut commands,
+        &materials,
+        &mut meshes,
+        map,
+        players.count,
+        config.player_hitbox_size,
+    );
+    map_spawned_events.send(MapSpawnedEvent {
+        name: map.name.clone(),
+        player_spawns,
+    });
+}
+
+// The tiles go and the spawn points are forgotten, so nothing spawns on them before the map of
+// the next game is in
+fn map_teardown(
+    mut commands: Commands,
+    mut monster_spawn_points: ResMut<MonsterSpawnPoints>,
+    mut player_spawn_points: ResMut<PlayerSpawnPoints>,
+    tile_query: Query<Entity, With<MapTileCmp>>,
+) {
+    for tile in tile_query.iter() {
+        commands.entity(tile).despawn();
+    }
+    monster_spawn_points.0.clear();
+    player_spawn_points.0.clear();
+}
+
+pub fn spawn_map(
+    commands: &mut Commands,
+    materials: &Res<Materials>,
+    meshes: &mut Assets<Mesh>,
+    map: &Map,
+    player_count: usize,
+    player_size: Vec2,
+) -> Vec<Vec2> {
+    let mut monster_spawn_points = Vec::new();
+    for (row, line) in map.map_string.lines().enumerate() {
+        for (col, char) in line.chars().enumerate() {
+            let sp = map.topleft
+                + Vec2::new(col as f32 * map.block_size.x, row as f32 * map.block_size.y);
+            match map.legend.get(char.as_char()) {
+                Some(TileKind::PlayerSpawn) => {}
+                Some(TileKind::MonsterSpawn) => monster_spawn_points.push(sp),
+                Some(kind) => spawn_tile(commands, materials, kind, sp, map.block_size),
+                None => {}
+            }
         }
-        col += 1_f32;
+    }
+    // the static walls are drawn in chunks and collide through the merged rectangles
+    spawn_wall_chunks(commands, materials, meshes, map);
+    for wall_rect in map.wall_rects() {
+        let sp = map.topleft
+            + Vec2::new(
+                wall_rect.col as f32 * map.block_size.x,
+                wall_rect.row as f32 * map.block_size.y,
+            );
+        let size = Vec2::new(
+            wall_rect.width as f32 * map.block_size.x,
+            wall_rect.height as f32 * map.block_size.y,
+        );
+        spawn_wall_collider(commands, materials, wall_rect.kind, sp, size);
+    }
+    for trigger in map.triggers.iter() {
+        spawn_trigger(
+            commands,
+            materials,
+            trigger.name.clone(),
+            map.topleft + trigger.offset,
+            trigger.size,
+        );
+    }
+    let player_spawn_points =
+        map.player_spawns(player_count, footprint_cells(player_size, map.block_size));
+    commands.insert_resource(MonsterSpawnPoints(monster_spawn_points));
+    commands.insert_resource(PlayerSpawnPoints(player_spawn_points.clone()));
+    player_spawn_points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the grid starts at line 6
+    fn map_text(rows: &[&str]) -> String {
+        format!(
+            "name: Test\nsize: {} {}\nblock_size: 20 20\ntopleft: 40 40\n---\n{}\n",
+            rows[0].len(),
+            rows.len(),
+            rows.join("\n")
+        )
+    }
+
+    fn error_of(text: &str) -> (usize, usize, MapErrorKind) {
+        let err = parse_map(text).unwrap_err();
+        (err.line, err.column, err.kind)
+    }
+
+    #[test]
+    fn parses_a_valid_map() {
+        assert!(parse_map(&map_text(&["*****", "*P M*", "*****"])).is_ok());
+    }
+
+    #[test]
+    fn header_errors() {
+        assert_eq!(
+            error_of("name: Test\nsize: 3 3\n"),
+            (3, 1, MapErrorKind::MissingSeparator)
+        );
+        assert_eq!(
+            error_of("name: Test\ncolor: red\n---\n"),
+            (2, 1, MapErrorKind::UnknownHeaderKey(STR("color")))
+        );
+        let err = parse_map("name: Test\nsize: 3\n---\n").unwrap_err();
+        assert_eq!(
+            (err.line, err.kind),
+            (
+                2,
+                MapErrorKind::BadNumbers {
+                    key: STR("size"),
+                    value: STR("3"),
+                }
+            )
+        );
+        let err = parse_map("name: Test\nlegend: ab = Wall\n---\n").unwrap_err();
+        assert_eq!(
+            (err.line, err.kind),
+            (2, MapErrorKind::BadLegend(STR("ab = Wall")))
+        );
+        assert_eq!(
+            error_of("name: Test\nsize: 3 3\ntopleft: 0 0\n---\n***\n"),
+            (4, 1, MapErrorKind::MissingHeaderKey("block_size"))
+        );
+    }
+
+    #[test]
+    fn grid_errors() {
+        assert_eq!(
+            error_of(&map_text(&["*****", "*P*", "*****"])),
+            (
+                7,
+                4,
+                MapErrorKind::RaggedRow {
+                    expected: 5,
+                    found: 3,
+                }
+            )
+        );
+        assert_eq!(
+            error_of(&map_text(&["*****", "*P?M*", "*****"])),
+            (7, 3, MapErrorKind::UnknownGlyph('?'))
+        );
+        assert_eq!(
+            error_of(&map_text(&["*****", " P M*", "*****"])),
+            (7, 1, MapErrorKind::OpenBorder(' '))
+        );
+        assert_eq!(
+            error_of(&map_text(&["*****", "*  M*", "*****"])),
+            (6, 1, MapErrorKind::MissingPlayerSpawn)
+        );
+    }
+
+    #[test]
+    fn size_has_to_match_the_grid() {
+        let text = map_text(&["*****", "*P M*", "*****"]).replace("size: 5 3", "size: 5 4");
+        assert_eq!(
+            parse_map(&text).unwrap_err().kind,
+            MapErrorKind::SizeMismatch {
+                declared: (5, 4),
+                actual: (5, 3),
+            }
+        );
+    }
+
+    #[test]
+    fn one_player_spawn_only() {
+        assert_eq!(
+            error_of(&map_text(&["*****", "*P P*", "*****"])),
+            (
+                7,
+                4,
+                MapErrorKind::DuplicatePlayerSpawn {
+                    first_line: 7,
+                    first_column: 2,
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn extra_players_spawn_next_to_the_player_spawn() {
+        let map = parse_map(&map_text(&["******", "*P   *", "*    *", "******"])).unwrap();
+        assert_eq!(
+            map.player_spawns(3, 1),
+            vec![
+                map.cell_topleft(1, 1),
+                map.cell_topleft(2, 1),
+                map.cell_topleft(1, 2),
+            ]
+        );
+        // a 2x2 player only fits right of or below the first one
+        assert_eq!(
+            map.player_spawns(2, 2),
+            vec![map.cell_topleft(1, 1), map.cell_topleft(3, 1)]
+        );
     }
 }
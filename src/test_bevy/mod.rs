@@ -1,20 +1,64 @@
 #![allow(unused)]
 
+mod actions;
+mod behavior_tree;
+mod camera;
 mod collision;
+mod editor;
 mod fps;
+mod globalconfig;
+mod health;
 mod helper;
+mod level;
+mod locomotion;
 mod map;
 mod monster;
+mod monster_ai;
+mod mouse_control;
+mod pathfinding;
 mod player;
+mod procgen;
+mod projectile;
+mod replay;
+mod rng;
+mod savegame;
+mod spawner;
+mod state;
+mod steering;
+mod tiled;
+mod tilemap;
+mod tiles;
 #[macro_use]
 mod vec2_wrapper;
 
+use actions::*;
+use behavior_tree::*;
+use camera::*;
 use collision::*;
+use editor::*;
 use fps::FpsPlugin;
+use globalconfig::*;
+use health::*;
 use helper::*;
-use map::MapPlugin;
-use monster::MonsterPlugin;
-use player::PlayerPlugin;
+use level::*;
+use locomotion::*;
+use map::*;
+use monster::*;
+use monster_ai::*;
+use mouse_control::*;
+use pathfinding::*;
+use player::*;
+use procgen::*;
+use projectile::*;
+use replay::*;
+use rng::*;
+use savegame::*;
+use spawner::*;
+use state::*;
+use steering::*;
+use tiled::*;
+use tilemap::*;
+use tiles::*;
 
 use fstrings::*;
 use std::{fmt, ops};
@@ -27,10 +71,22 @@ const TIME_STEP: f32 = 1. / 60.;
 
 // Shared Resources
 pub struct Materials {
-    player_materials: Handle<ColorMaterial>,
+    // one per local player
+    player_materials: Vec<Handle<ColorMaterial>>,
     monster_materials: Handle<ColorMaterial>,
     wall_materials: Handle<ColorMaterial>,
+    stop_wall_materials: Handle<ColorMaterial>,
+    reflect_wall_materials: Handle<ColorMaterial>,
+    destructible_wall_materials: Handle<ColorMaterial>,
+    door_materials: Handle<ColorMaterial>,
+    pickup_materials: Handle<ColorMaterial>,
+    trigger_materials: Handle<ColorMaterial>,
+    exit_materials: Handle<ColorMaterial>,
     ray_materials: Handle<ColorMaterial>,
+    projectile_materials: Handle<ColorMaterial>,
+    editor_cursor_materials: Handle<ColorMaterial>,
+    // dims the game behind the menu, pause and game over screens
+    screen_materials: Handle<ColorMaterial>,
 }
 #[derive(Debug)]
 pub struct GameWindowSize {
@@ -95,6 +151,13 @@ pub enum CollideType {
 pub struct CollideCmp {
     property: u32,
 }
+
+#[derive(Debug)]
+pub struct HealthCmp {
+    current: f32,
+    max: f32,
+    iframes: f32,
+}
 // Components
 
 // Entities
@@ -105,22 +168,40 @@ pub struct RayEntity;
 // Entities
 
 pub fn bevy_main() {
+    // before DefaultPlugins, the window is created from it
+    let config = GlobalConfig::from_args();
     App::build()
-        .insert_resource(ClearColor(Color::rgb(0., 0., 0.)))
-        .insert_resource(WindowDescriptor {
-            title: STR("Example Breakout"),
-            width: 600.,
-            height: 600.,
-            ..Default::default()
-        })
+        .insert_resource(ClearColor(ColorConfig::color(config.colors.clear)))
+        .insert_resource(config.window_descriptor())
+        .insert_resource(GameRng::from_args(&config))
+        .insert_resource(config)
+        .insert_resource(LocalPlayers::from_args())
         .add_plugins(DefaultPlugins)
+        .add_plugin(GlobalConfigPlugin)
+        .add_plugin(ActionsPlugin)
+        .add_plugin(GameStatePlugin)
         .add_plugin(PlayerPlugin)
+        .add_plugin(LocomotionPlugin)
+        .add_plugin(ProjectilePlugin)
+        .add_plugin(HealthPlugin)
+        .add_plugin(MouseControlPlugin)
         .add_plugin(MonsterPlugin)
+        .add_plugin(MonsterAiPlugin)
+        .add_plugin(BehaviorTreePlugin)
+        .add_plugin(SteeringPlugin)
+        .add_plugin(SpawnerPlugin)
         .add_plugin(MapPlugin)
+        .add_plugin(LevelPlugin)
+        // after the plugins whose resources a replay overrides
+        .add_plugin(ReplayPlugin)
+        .add_plugin(SaveGamePlugin)
+        .add_plugin(CameraPlugin)
+        .add_plugin(TilesPlugin)
+        .add_plugin(EditorPlugin)
         .add_plugin(FpsPlugin)
         .add_startup_system(setup.system())
         .add_system(get_window_size.system())
-        .add_system(get_cursor_pos.system())
+        .add_system(get_cursor_pos.system().label("cursor_position_system"))
         .run();
 }
 
@@ -129,20 +210,54 @@ fn setup(
     asset_server: Res<AssetServer>,
     mut materials: ResMut<Assets<ColorMaterial>>,
     mut windows: ResMut<Windows>,
+    config: Res<GlobalConfig>,
 ) {
-    // camera
-    commands.spawn_bundle(OrthographicCameraBundle::new_2d());
-
     // position window
     let mut window = windows.get_primary_mut().unwrap();
     window.set_position(IVec2::new(0, 0));
 
+    // camera, starts out showing the window-sized area at the world's origin
+    let mut camera = OrthographicCameraBundle::new_2d();
+    let camera_sp = world_to_bevy(&Point::new((window.width() / 2., window.height() / 2.)));
+    camera.transform.translation.x = camera_sp.x();
+    camera.transform.translation.y = camera_sp.y();
+    commands
+        .spawn_bundle(camera)
+        .insert(MainCameraEntity)
+        .insert(CameraFollowCmp::new(&config.camera));
+
     // create main resources
+    let wall_atlas = asset_server.load(WALL_ATLAS_PATH);
+    let colors = &config.colors;
+    let color = ColorConfig::color;
     commands.insert_resource(Materials {
-        player_materials: materials.add(Color::rgb(1., 0.7, 0.7).into()),
-        monster_materials: materials.add(Color::rgb(0.2, 0.2, 0.7).into()),
-        wall_materials: materials.add(Color::rgb(1., 1., 1.).into()),
-        ray_materials: materials.add(Color::rgb(1., 1., 1.).into()),
+        player_materials: colors
+            .players
+            .iter()
+            .map(|rgba| materials.add(color(*rgba).into()))
+            .collect(),
+        monster_materials: materials.add(color(colors.monster).into()),
+        wall_materials: materials.add(ColorMaterial::modulated_texture(
+            wall_atlas.clone(),
+            color(colors.wall),
+        )),
+        stop_wall_materials: materials.add(ColorMaterial::modulated_texture(
+            wall_atlas.clone(),
+            color(colors.stop_wall),
+        )),
+        reflect_wall_materials: materials.add(ColorMaterial::modulated_texture(
+            wall_atlas,
+            color(colors.reflect_wall),
+        )),
+        destructible_wall_materials: materials.add(color(colors.destructible_wall).into()),
+        door_materials: materials.add(color(colors.door).into()),
+        pickup_materials: materials.add(color(colors.pickup).into()),
+        trigger_materials: materials.add(color(colors.trigger).into()),
+        exit_materials: materials.add(color(colors.exit).into()),
+        ray_materials: materials.add(color(colors.ray).into()),
+        projectile_materials: materials.add(color(colors.projectile).into()),
+        editor_cursor_materials: materials.add(color(colors.editor_cursor).into()),
+        screen_materials: materials.add(color(colors.screen).into()),
     });
     commands.insert_resource(GameWindowSize {
         w: window.width(),
@@ -158,21 +273,36 @@ fn get_window_size(mut win_size: ResMut<GameWindowSize>, mut events: EventReader
     }
 }
 
+// The cursor in world coordinates, going through the camera. A replay puts back the recorded one.
 fn get_cursor_pos(
     windows: Res<Windows>,
     win_size: Res<GameWindowSize>,
+    playback: Option<Res<ReplayPlayback>>,
     mut cursor_pos: ResMut<CursorPosition>,
     mut events: EventReader<CursorMoved>,
+    camera_query: Query<&Transform, With<MainCameraEntity>>,
 ) {
+    if let Some(playback) = playback {
+        let cursor = playback.frame().and_then(|frame| frame.cursor);
+        cursor_pos.x = cursor.map(|(x, _)| x);
+        cursor_pos.y = cursor.map(|(_, y)| y);
+        return;
+    }
     let window = windows.get_primary().unwrap();
-    if let Some(cursor_pos_) = window.cursor_position() {
-        let converted_pos = botleft_to_toplef_origin(&Point(cursor_pos_), &win_size);
-        cursor_pos.x = Some(converted_pos.x());
-        cursor_pos.y = Some(converted_pos.y());
-        // dbg!((cursor_pos.x, cursor_pos.y));
-    } else {
-        cursor_pos.x = None;
-        cursor_pos.y = None;
+    match (window.cursor_position(), camera_query.single()) {
+        (Some(cursor_pos_), Ok(camera_tf)) => {
+            // the screen position relative to the screen center, then into the camera's view
+            let from_center = cursor_pos_ - Vec2::new(win_size.w / 2., win_size.h / 2.);
+            let bevy_pos = camera_tf.compute_matrix() * from_center.extend(0.).extend(1.);
+            let converted_pos = bevy_to_world(&Point::new((bevy_pos.x, bevy_pos.y)));
+            cursor_pos.x = Some(converted_pos.x());
+            cursor_pos.y = Some(converted_pos.y());
+            // dbg!((cursor_pos.x, cursor_pos.y));
+        }
+        _ => {
+            cursor_pos.x = None;
+            cursor_pos.y = None;
+        }
     }
 }
 
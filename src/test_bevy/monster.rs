@@ -1,5 +1,4 @@
-use bevy::core::FixedTimestep;
-use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
 
 use bevy::input::*;
 use bevy::prelude::*;
@@ -11,36 +10,108 @@ pub struct MonsterPlugin;
 
 impl Plugin for MonsterPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_startup_stage(
-            "game_setup_monster",
-            SystemStage::single(monster_spawn.system()),
+        app.add_system_set(
+            SystemSet::on_update(AppState::Playing)
+                .with_system(monster_collision.system().label("monster_collision_system"))
+                .with_system(
+                    monster_update_hitbox
+                        .system()
+                        .label("monster_update_hitbox_system")
+                        .after("monster_collision_system"),
+                ),
         )
         .add_system_set(
-            SystemSet::new()
-                .label("monster_movement_system")
-                .with_run_criteria(FixedTimestep::step(0.3))
-                .with_system(monster_movement.system()),
-        )
-        .add_system(monster_collision.system().label("monster_collision_system"))
-        .add_system(
-            monster_update_hitbox
-                .system()
-                .label("monster_update_hitbox_system")
-                .after("monster_collision_system"),
+            SystemSet::on_exit(AppState::Playing).with_system(monster_teardown.system()),
         );
     }
 }
 
-fn monster_spawn(mut commands: Commands, materials: Res<Materials>, win_size: Res<GameWindowSize>) {
-    let (monster_w, monster_h): (f32, f32) = (35., 35.);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MonsterArchetype {
+    Grunt,
+    Coward,
+    Berserker,
+    Stalker,
+    Skirmisher,
+}
+
+impl MonsterArchetype {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MonsterArchetype::Grunt => "Grunt",
+            MonsterArchetype::Coward => "Coward",
+            MonsterArchetype::Berserker => "Berserker",
+            MonsterArchetype::Stalker => "Stalker",
+            MonsterArchetype::Skirmisher => "Skirmisher",
+        }
+    }
+
+    pub fn ai_config(&self) -> MonsterAiConfig {
+        match self {
+            MonsterArchetype::Grunt => MonsterAiConfig::grunt(),
+            MonsterArchetype::Coward => MonsterAiConfig::coward(),
+            MonsterArchetype::Berserker => MonsterAiConfig::berserker(),
+            _ => MonsterAiConfig::default(),
+        }
+    }
+
+    // Archetypes authored as data, their behavior tree replaces the MonsterAiCmp state machine
+    pub fn behavior_tree(&self) -> Option<&'static str> {
+        match self {
+            MonsterArchetype::Stalker => Some("behaviors/stalker.bt.ron"),
+            MonsterArchetype::Skirmisher => Some("behaviors/skirmisher.bt.json"),
+            _ => None,
+        }
+    }
+
+    pub fn max_health(&self) -> f32 {
+        match self {
+            MonsterArchetype::Grunt => 100.,
+            MonsterArchetype::Coward => 60.,
+            MonsterArchetype::Berserker => 200.,
+            MonsterArchetype::Stalker => 120.,
+            MonsterArchetype::Skirmisher => 80.,
+        }
+    }
+
+    // points for killing one
+    pub fn bounty(&self) -> u32 {
+        match self {
+            MonsterArchetype::Grunt => 100,
+            MonsterArchetype::Coward => 150,
+            MonsterArchetype::Berserker => 300,
+            MonsterArchetype::Stalker => 200,
+            MonsterArchetype::Skirmisher => 200,
+        }
+    }
+
+    // what touching the player costs it
+    pub fn contact_damage(&self) -> f32 {
+        match self {
+            MonsterArchetype::Grunt => 10.,
+            MonsterArchetype::Coward => 5.,
+            MonsterArchetype::Berserker => 25.,
+            MonsterArchetype::Stalker => 15.,
+            MonsterArchetype::Skirmisher => 10.,
+        }
+    }
+}
+
+pub fn spawn_monster(
+    commands: &mut Commands,
+    materials: &Res<Materials>,
+    config: &Res<GlobalConfig>,
+    sp: Point,
+    archetype: MonsterArchetype,
+) -> Entity {
+    let (monster_w, monster_h): (f32, f32) = config.monster_hitbox_size.into();
     // sp = spawn point - in the top-left origin coordinate
-    let mut sp = Point::new((100., 100.));
     // convert sp to bevy's sp
-    let mut bevy_sp = topleft_to_mid_origin(&sp, &win_size);
+    let mut bevy_sp = world_to_bevy(&sp);
     // since bevy spawns's center matches its sp, we need our sp to be its top-left corner
     bevy_sp = bevy_sp + Point::new((monster_w / 2., -monster_h / 2.));
 
-    commands
+    let monster = commands
         .spawn_bundle(SpriteBundle {
             material: materials.monster_materials.clone(),
             sprite: Sprite::new(Vec2::new(monster_w, monster_h)),
@@ -48,66 +119,39 @@ fn monster_spawn(mut commands: Commands, materials: Res<Materials>, win_size: Re
             ..Default::default()
         })
         .insert(MonsterEntity)
-        .insert(NameCmp(STR("ItsMe")))
+        .insert(archetype)
+        .insert(NameCmp(STR(archetype.name())))
         .insert(MovementCmp {
             position: sp.0,
-            speed: Vec2::new(600., 600.),
+            speed: config.monster_speed(archetype),
             direction: Vec2::new(1., 1.),
             delta: Vec2::new(0., 0.),
         })
         .insert(RectangleHitboxCmp {
             rect: collision::Rect::new((sp, monster_w, monster_h)),
-        });
+        })
+        .insert(HealthCmp::new(archetype.max_health(), 0.))
+        .insert(ContactDamageCmp {
+            amount: archetype.contact_damage(),
+        })
+        .insert(MonsterAiCmp::new(archetype.ai_config(), sp.0))
+        .insert(SteeringCmp::flock())
+        .id();
 
-    println!("Spawned Monster at ({} -> {})", sp, bevy_sp);
-}
-
-fn monster_movement(
-    mut monster_query: Query<(&mut MovementCmp, &mut Transform), With<MonsterEntity>>,
-) {
-    if let Ok((mut monster_mov, mut monster_tf)) = monster_query.single_mut() {
-        let mut rng = thread_rng();
-        let move_arr: [u32; 8] = [8, 4, 2, 1, 10, 9, 6, 5];
-        let dir_choice: usize = rng.gen_range(0..8);
-
-        // let move_arr: [u32; 4] = [8, 4, 2, 1];
-        // let dir_choice: usize = rng.gen_range(0..4);
-
-        let mut direction = match move_arr[dir_choice] {
-            8 => Vec2::new(0., -1.),
-            4 => Vec2::new(0., 1.),
-            2 => Vec2::new(-1., 0.),
-            1 => Vec2::new(1., 0.),
-            10 => Vec2::new(-1., -1.),
-            9 => Vec2::new(1., -1.),
-            6 => Vec2::new(-1., 1.),
-            5 => Vec2::new(1., 1.),
-            _ => Vec2::new(0., 0.),
-        };
-
-        monster_mov.direction = direction;
-    }
+    println!("Spawned {} at ({} -> {})", archetype.name(), sp, bevy_sp);
+    monster
 }
 
 fn monster_collision(
     mut commands: Commands,
-    mut materials: ResMut<Assets<ColorMaterial>>,
     windows: Res<Windows>,
     mut monster_query: Query<
         (&mut MovementCmp, &RectangleHitboxCmp, &mut Transform),
         With<MonsterEntity>,
     >,
-    mut wall_query: Query<
-        (
-            Entity,
-            &RectangleHitboxCmp,
-            &CollideCmp,
-            &Handle<ColorMaterial>,
-        ),
-        With<WallEntity>,
-    >,
+    wall_query: Query<(Entity, &RectangleHitboxCmp, &CollideCmp), With<WallEntity>>,
 ) {
-    if let Ok((mut monster_mov, monster_hitbox, mut monster_tf)) = monster_query.single_mut() {
+    for (mut monster_mov, monster_hitbox, mut monster_tf) in monster_query.iter_mut() {
         let mut delta = monster_mov.direction * monster_mov.speed * TIME_STEP;
 
         // reset direction
@@ -115,9 +159,7 @@ fn monster_collision(
 
         let mut contact_normals = 0_u32;
 
-        for (wall_entity, wall_hitbox, wall_collide, wall_color) in wall_query.iter_mut() {
-            let color = &mut materials.get_mut(wall_color).unwrap().color;
-
+        for (wall_entity, wall_hitbox, wall_collide) in wall_query.iter() {
             let collided2 = wall_hitbox.rect.is_rect_intersect(&monster_hitbox.rect);
 
             let (collided, contact_point, contact_normal, contact_time) =
@@ -129,8 +171,6 @@ fn monster_collision(
                 );
 
             if collided {
-                set_bevy_color_rgba(color, 0., 1., 1., 1.);
-
                 // resolve the collision
                 // Only add to delta unique normals since the moving block can collide with multiple
                 // blocks resulting in duplication of the delta += .. statement.
@@ -153,8 +193,6 @@ fn monster_collision(
                     }
                 }
                 contact_normals = contact_normals_new;
-            } else {
-                set_bevy_color_rgba(color, 1., 1., 1., 1.);
             }
         }
         if next_direction.cmpne(Vec2::ZERO).all() {
@@ -171,9 +209,15 @@ fn monster_collision(
 fn monster_update_hitbox(
     mut monster_query: Query<(&MovementCmp, &mut RectangleHitboxCmp), With<MonsterEntity>>,
 ) {
-    if let Ok((monster_mov, mut monster_hitbox)) = monster_query.single_mut() {
+    for (monster_mov, mut monster_hitbox) in monster_query.iter_mut() {
         monster_hitbox
             .rect
             .r#move(monster_mov.delta.x, monster_mov.delta.y);
     }
 }
+
+fn monster_teardown(mut commands: Commands, monster_query: Query<Entity, With<MonsterEntity>>) {
+    for monster in monster_query.iter() {
+        commands.entity(monster).despawn_recursive();
+    }
+}
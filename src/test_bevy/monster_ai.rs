@@ -0,0 +1,369 @@
+use serde::{Deserialize, Serialize};
+
+use bevy::input::*;
+use bevy::prelude::*;
+use bevy::window::*;
+
+use crate::test_bevy::*;
+
+pub struct MonsterAiPlugin;
+
+impl Plugin for MonsterAiPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_set(
+            SystemSet::on_update(AppState::Playing).with_system(
+                monster_ai
+                    .system()
+                    .label("monster_ai_system")
+                    .before("monster_collision_system"),
+            ),
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MonsterAiState {
+    Wander,
+    Chase,
+    Flee,
+    Attack,
+    ReturnHome,
+}
+
+// Thresholds that drive the state transitions. Each monster type gets its own preset.
+#[derive(Debug, Clone)]
+pub struct MonsterAiConfig {
+    // how far the monster can spot the player (needs line of sight)
+    pub sight_range: f32,
+    // how close the player has to be before the monster stops and attacks
+    pub attack_range: f32,
+    // how far the monster is willing to stray from home while chasing
+    pub leash_range: f32,
+    // health ratio (current / max) under which the monster runs away. 0 = never flee
+    pub flee_health_ratio: f32,
+    // how close to home counts as being home
+    pub home_tolerance: f32,
+}
+
+impl MonsterAiConfig {
+    pub fn grunt() -> Self {
+        Self {
+            sight_range: 200.,
+            attack_range: 40.,
+            leash_range: 300.,
+            flee_health_ratio: 0.25,
+            home_tolerance: 10.,
+        }
+    }
+
+    pub fn coward() -> Self {
+        Self {
+            sight_range: 250.,
+            attack_range: 30.,
+            leash_range: 150.,
+            flee_health_ratio: 0.6,
+            home_tolerance: 10.,
+        }
+    }
+
+    pub fn berserker() -> Self {
+        Self {
+            sight_range: 300.,
+            attack_range: 45.,
+            leash_range: f32::INFINITY,
+            flee_health_ratio: 0.,
+            home_tolerance: 10.,
+        }
+    }
+}
+
+impl Default for MonsterAiConfig {
+    fn default() -> Self {
+        Self::grunt()
+    }
+}
+
+pub struct MonsterAiCmp {
+    pub state: MonsterAiState,
+    pub config: MonsterAiConfig,
+    // where the monster spawned (top-left origin), used by ReturnHome and the leash
+    pub home: Vec2,
+}
+
+impl MonsterAiCmp {
+    pub fn new(config: MonsterAiConfig, home: Vec2) -> Self {
+        Self {
+            state: MonsterAiState::Wander,
+            config,
+            home,
+        }
+    }
+}
+
+// Everything the state machine needs to know about the world for one monster
+pub struct MonsterAiSenses {
+    pub distance_to_player: Option<f32>,
+    pub can_see_player: bool,
+    pub distance_to_home: f32,
+    pub health_ratio: f32,
+}
+
+pub fn next_monster_ai_state(
+    state: MonsterAiState,
+    config: &MonsterAiConfig,
+    senses: &MonsterAiSenses,
+) -> MonsterAiState {
+    let player_in_sight = senses.can_see_player
+        && senses
+            .distance_to_player
+            .is_some_and(|d| d <= config.sight_range);
+    let player_in_reach = player_in_sight
+        && senses
+            .distance_to_player
+            .is_some_and(|d| d <= config.attack_range);
+    let beyond_leash = senses.distance_to_home > config.leash_range;
+    let at_home = senses.distance_to_home <= config.home_tolerance;
+
+    // only runs from a player it can see, otherwise a wounded monster goes about its business
+    if player_in_sight && senses.health_ratio < config.flee_health_ratio {
+        return MonsterAiState::Flee;
+    }
+
+    match state {
+        MonsterAiState::Wander => {
+            if player_in_reach {
+                MonsterAiState::Attack
+            } else if player_in_sight {
+                MonsterAiState::Chase
+            } else {
+                MonsterAiState::Wander
+            }
+        }
+        MonsterAiState::Chase => {
+            if beyond_leash || !player_in_sight {
+                MonsterAiState::ReturnHome
+            } else if player_in_reach {
+                MonsterAiState::Attack
+            } else {
+                MonsterAiState::Chase
+            }
+        }
+        MonsterAiState::Attack => {
+            if player_in_reach {
+                MonsterAiState::Attack
+            } else if player_in_sight && !beyond_leash {
+                MonsterAiState::Chase
+            } else {
+                MonsterAiState::ReturnHome
+            }
+        }
+        // a fleeing monster heals nowhere, so it keeps running until it is out of sight, then
+        // heads home
+        MonsterAiState::Flee => {
+            if player_in_sight {
+                MonsterAiState::Flee
+            } else {
+                MonsterAiState::ReturnHome
+            }
+        }
+        MonsterAiState::ReturnHome => {
+            if player_in_sight && !beyond_leash {
+                MonsterAiState::Chase
+            } else if at_home {
+                MonsterAiState::Wander
+            } else {
+                MonsterAiState::ReturnHome
+            }
+        }
+    }
+}
+
+// Where a monster wants to go this frame. Positions are hitbox centers in top-left origin.
+#[derive(Debug, Clone, Copy)]
+pub enum MonsterMoveIntent {
+    Wander,
+    Seek(Vec2),
+    Flee(Vec2),
+    Hold,
+}
+
+// Monsters only get told where to go and the steering system turns that into a direction.
+pub fn apply_monster_move_intent(
+    intent: MonsterMoveIntent,
+    center: Vec2,
+    steering: &mut SteeringCmp,
+) {
+    let (seek_target, flee_target) = match intent {
+        MonsterMoveIntent::Wander => (None, None),
+        MonsterMoveIntent::Seek(target) => (Some(target), None),
+        MonsterMoveIntent::Flee(target) => (None, Some(target)),
+        MonsterMoveIntent::Hold => (Some(center), None),
+    };
+    steering.seek_target = seek_target;
+    steering.flee_target = flee_target;
+}
+
+// The player a monster cares about is the closest one
+pub fn nearest_player_center(monster_center: &Point, player_centers: &[Point]) -> Option<Point> {
+    player_centers.iter().copied().min_by(|a, b| {
+        a.distance(monster_center)
+            .partial_cmp(&b.distance(monster_center))
+            .unwrap_or(std::cmp::Ordering::Equal)
+    })
+}
+
+pub fn sense_monster_surroundings<'a, I>(
+    monster_center: &Point,
+    monster_mov: &MovementCmp,
+    home: Vec2,
+    monster_health: Option<&HealthCmp>,
+    player_center: Option<Point>,
+    walls: I,
+) -> MonsterAiSenses
+where
+    I: IntoIterator<Item = &'a collision::Rect>,
+{
+    let distance_to_player = player_center.map(|p| p.distance(monster_center));
+    let can_see_player = match player_center {
+        Some(p) => !is_line_of_sight_blocked(monster_center, &p, walls),
+        None => false,
+    };
+    MonsterAiSenses {
+        distance_to_player,
+        can_see_player,
+        distance_to_home: monster_mov.position.distance(home),
+        health_ratio: monster_health.map_or(1., |h| h.current / h.max),
+    }
+}
+
+fn monster_ai(
+    player_query: Query<&RectangleHitboxCmp, With<PlayerEntity>>,
+    mut monster_query: Query<
+        (
+            &mut MonsterAiCmp,
+            &MovementCmp,
+            &RectangleHitboxCmp,
+            Option<&HealthCmp>,
+            &mut SteeringCmp,
+        ),
+        With<MonsterEntity>,
+    >,
+    wall_query: Query<&RectangleHitboxCmp, With<WallEntity>>,
+) {
+    let player_centers: Vec<Point> = player_query
+        .iter()
+        .map(|player_hitbox| player_hitbox.rect.center())
+        .collect();
+
+    for (mut monster_ai, monster_mov, monster_hitbox, monster_health, mut monster_steering) in
+        monster_query.iter_mut()
+    {
+        let monster_center = monster_hitbox.rect.center();
+        let player_center = nearest_player_center(&monster_center, &player_centers);
+        let senses = sense_monster_surroundings(
+            &monster_center,
+            monster_mov,
+            monster_ai.home,
+            monster_health,
+            player_center,
+            wall_query.iter().map(|wall_hitbox| &wall_hitbox.rect),
+        );
+
+        monster_ai.state = next_monster_ai_state(monster_ai.state, &monster_ai.config, &senses);
+
+        let player_target = player_center.map(|p| p.get_vec2());
+        // home is a top-left corner while intents work with centers
+        let home_center = monster_ai.home + (monster_center - monster_mov.position).get_vec2();
+        let intent = match (monster_ai.state, player_target) {
+            (MonsterAiState::Wander, _) => MonsterMoveIntent::Wander,
+            (MonsterAiState::Chase, Some(target)) => MonsterMoveIntent::Seek(target),
+            (MonsterAiState::Flee, Some(target)) => MonsterMoveIntent::Flee(target),
+            (MonsterAiState::Chase, None) | (MonsterAiState::Flee, None) => MonsterMoveIntent::Hold,
+            (MonsterAiState::Attack, _) => MonsterMoveIntent::Hold,
+            (MonsterAiState::ReturnHome, _) => MonsterMoveIntent::Seek(home_center),
+        };
+        apply_monster_move_intent(intent, monster_center.get_vec2(), &mut monster_steering);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn senses(distance_to_player: Option<f32>, distance_to_home: f32) -> MonsterAiSenses {
+        MonsterAiSenses {
+            distance_to_player,
+            can_see_player: distance_to_player.is_some(),
+            distance_to_home,
+            health_ratio: 1.,
+        }
+    }
+
+    fn next(state: MonsterAiState, senses: &MonsterAiSenses) -> MonsterAiState {
+        next_monster_ai_state(state, &MonsterAiConfig::grunt(), senses)
+    }
+
+    #[test]
+    fn wander_until_the_player_shows_up() {
+        use MonsterAiState::*;
+        assert_eq!(next(Wander, &senses(None, 0.)), Wander);
+        // out of sight range
+        assert_eq!(next(Wander, &senses(Some(250.), 0.)), Wander);
+        assert_eq!(next(Wander, &senses(Some(150.), 0.)), Chase);
+        assert_eq!(next(Wander, &senses(Some(30.), 0.)), Attack);
+
+        // in range but behind a wall
+        let mut hidden = senses(Some(30.), 0.);
+        hidden.can_see_player = false;
+        assert_eq!(next(Wander, &hidden), Wander);
+    }
+
+    #[test]
+    fn chase_and_attack() {
+        use MonsterAiState::*;
+        assert_eq!(next(Chase, &senses(Some(150.), 100.)), Chase);
+        assert_eq!(next(Chase, &senses(Some(30.), 100.)), Attack);
+        assert_eq!(next(Attack, &senses(Some(30.), 100.)), Attack);
+        assert_eq!(next(Attack, &senses(Some(150.), 100.)), Chase);
+        assert_eq!(next(Attack, &senses(None, 100.)), ReturnHome);
+    }
+
+    #[test]
+    fn give_up_and_go_home() {
+        use MonsterAiState::*;
+        // lost sight of the player
+        assert_eq!(next(Chase, &senses(None, 100.)), ReturnHome);
+        // too far from home
+        assert_eq!(next(Chase, &senses(Some(150.), 350.)), ReturnHome);
+        assert_eq!(next(ReturnHome, &senses(Some(150.), 350.)), ReturnHome);
+        assert_eq!(next(ReturnHome, &senses(None, 100.)), ReturnHome);
+        assert_eq!(next(ReturnHome, &senses(Some(150.), 100.)), Chase);
+        assert_eq!(next(ReturnHome, &senses(None, 5.)), Wander);
+        // ran far enough
+        assert_eq!(next(Flee, &senses(None, 100.)), ReturnHome);
+    }
+
+    #[test]
+    fn flee_when_hurt() {
+        use MonsterAiState::*;
+        let mut hurt = senses(Some(150.), 100.);
+        hurt.health_ratio = 0.2;
+        for state in [Wander, Chase, Attack, Flee, ReturnHome].iter() {
+            assert_eq!(next(*state, &hurt), Flee);
+        }
+        // the berserker never runs
+        assert_eq!(
+            next_monster_ai_state(Chase, &MonsterAiConfig::berserker(), &hurt),
+            Chase
+        );
+    }
+
+    #[test]
+    fn no_fleeing_from_an_unseen_player() {
+        use MonsterAiState::*;
+        let mut hurt = senses(None, 100.);
+        hurt.health_ratio = 0.2;
+        assert_eq!(next(Wander, &hurt), Wander);
+        assert_eq!(next(ReturnHome, &hurt), ReturnHome);
+    }
+}
@@ -0,0 +1,256 @@
+use bevy::input::*;
+use bevy::prelude::*;
+use bevy::window::*;
+
+use crate::test_bevy::*;
+
+pub struct MouseControlPlugin;
+
+impl Plugin for MouseControlPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(ControlScheme::Keyboard).add_system_set(
+            SystemSet::on_update(AppState::Playing)
+                .with_system(
+                    control_scheme_switch
+                        .system()
+                        .label("control_scheme_switch_system")
+                        .after("input_actions_system"),
+                )
+                .with_system(
+                    click_move
+                        .system()
+                        .label("click_move_system")
+                        .after("control_scheme_switch_system")
+                        .after("player_movement_system")
+                        .before("locomotion_system"),
+                )
+                .with_system(
+                    player_aim
+                        .system()
+                        .label("player_aim_system")
+                        .after("player_update_hitbox_system")
+                        .before("player_fire_system"),
+                ),
+        );
+    }
+}
+
+// How the player with the mouse moves. Aiming follows the mouse either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlScheme {
+    // the move actions, WASD / stick by default
+    Keyboard,
+    // MoveTo (right click) walks there around the walls, the move actions still take over
+    Mouse,
+}
+
+// Where the player is walking to with the mouse control scheme
+#[derive(Default)]
+pub struct ClickMoveCmp {
+    // top-left positions to pass through, the next one first
+    pub path: Vec<Vec2>,
+}
+
+// Where the player points, a unit vector in world coordinates
+pub struct AimCmp {
+    pub direction: Vec2,
+}
+
+impl Default for AimCmp {
+    fn default() -> Self {
+        Self {
+            direction: Vec2::new(1., 0.),
+        }
+    }
+}
+
+// Small marker at the player's edge showing the aim, a child of the player
+pub struct AimIndicatorEntity;
+
+// the player with the mouse (and WASD) in its default bindings, the others aim where they walk
+pub const MOUSE_PLAYER: usize = 0;
+
+const AIM_INDICATOR_DISTANCE: f32 = 25.;
+// closer than this to a waypoint counts as there
+const WAYPOINT_REACHED: f32 = 4.;
+// the player slows down this far from the destination
+const ARRIVE_RADIUS: f32 = 40.;
+
+pub fn spawn_aim_indicator(parent: &mut ChildBuilder, materials: &Res<Materials>) {
+    parent
+        .spawn_bundle(SpriteBundle {
+            material: materials.ray_materials.clone(),
+            sprite: Sprite::new(Vec2::new(6., 6.)),
+            transform: Transform::from_xyz(AIM_INDICATOR_DISTANCE, 0., 0.5),
+            ..Default::default()
+        })
+        .insert(AimIndicatorEntity);
+}
+
+// The top-left positions of a walk from `from` to `to` around the walls, empty if there is no way
+pub fn plan_click_move<'a, I>(map: &Map, walls: I, size: Vec2, from: Vec2, to: Vec2) -> Vec<Vec2>
+where
+    I: IntoIterator<Item = &'a collision::Rect>,
+{
+    let block_size = map.block_size();
+    // cells the entity covers, it walks with its top-left corner on the cells
+    let footprint = footprint_cells(size, block_size);
+    let walkable = clearance_grid(&blocked_grid(map, walls), footprint);
+    // centered in its footprint, so there is some room on every side
+    let margin = (Vec2::new(footprint as f32, footprint as f32) * block_size - size) / 2.;
+
+    let cell = |position: Vec2| {
+        map.cell_at(position + margin)
+            .and_then(|cell| nearest_walkable(&walkable, cell))
+    };
+    let path = match (cell(from), cell(to)) {
+        (Some(start), Some(goal)) => find_grid_path(&walkable, start, goal),
+        _ => None,
+    };
+    match path {
+        Some(path) => simplify_grid_path(&path)
+            .into_iter()
+            // already standing on the first one
+            .skip(1)
+            .map(|(col, row)| map.cell_topleft(col, row) + margin)
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+fn control_scheme_switch(
+    action_state: Res<ActionState>,
+    mut scheme: ResMut<ControlScheme>,
+    mut player_query: Query<&mut ClickMoveCmp, With<PlayerEntity>>,
+) {
+    let switched = action_state
+        .player(MOUSE_PLAYER)
+        .is_some_and(|actions| actions.just_pressed(InputAction::SwitchControls));
+    if !switched {
+        return;
+    }
+    *scheme = match *scheme {
+        ControlScheme::Keyboard => ControlScheme::Mouse,
+        ControlScheme::Mouse => ControlScheme::Keyboard,
+    };
+    for mut click_move in player_query.iter_mut() {
+        click_move.path.clear();
+    }
+    println!("Control scheme: {:?}", *scheme);
+}
+
+fn click_move(
+    action_state: Res<ActionState>,
+    scheme: Res<ControlScheme>,
+    cursor_pos: Res<CursorPosition>,
+    editor: Res<MapEditor>,
+    maps: Res<Assets<Map>>,
+    current_map: Option<Res<CurrentMap>>,
+    mut map_spawned_events: EventReader<MapSpawnedEvent>,
+    mut player_query: Query<
+        (
+            &PlayerIdCmp,
+            &MovementCmp,
+            &RectangleHitboxCmp,
+            &mut LocomotionCmp,
+            &mut ClickMoveCmp,
+        ),
+        With<PlayerEntity>,
+    >,
+    wall_query: Query<(&RectangleHitboxCmp, &CollideCmp), With<WallEntity>>,
+) {
+    let new_map = map_spawned_events.iter().count() > 0;
+    let actions = match action_state.player(MOUSE_PLAYER) {
+        Some(actions) => actions,
+        None => return,
+    };
+    let mouse_player = player_query
+        .iter_mut()
+        .find(|(player_id, ..)| player_id.0 == MOUSE_PLAYER);
+    if let Some((_, player_mov, player_hitbox, mut player_locomotion, mut click_move)) =
+        mouse_player
+    {
+        // a path of the old map leads nowhere, and the clicks paint tiles while the map editor
+        // is open
+        if new_map || *scheme != ControlScheme::Mouse || editor.active {
+            click_move.path.clear();
+            return;
+        }
+        // the move actions take over
+        if player_locomotion.input != Vec2::ZERO {
+            click_move.path.clear();
+            return;
+        }
+
+        let map = current_map.and_then(|current_map| maps.get(&current_map.0));
+        if let (true, Some(x), Some(y), Some(map)) = (
+            actions.just_pressed(InputAction::MoveTo),
+            cursor_pos.x,
+            cursor_pos.y,
+            map,
+        ) {
+            let size = Vec2::new(player_hitbox.rect.width, player_hitbox.rect.height);
+            let walls: Vec<collision::Rect> = wall_query
+                .iter()
+                .filter(|(_, wall_collide)| wall_collide.property != CollideType::None as u32)
+                .map(|(wall_hitbox, _)| wall_hitbox.rect)
+                .collect();
+            // the click is where the player's center should end up
+            let target = Vec2::new(x, y) - size / 2.;
+            click_move.path = plan_click_move(map, walls.iter(), size, player_mov.position, target);
+            if click_move.path.is_empty() {
+                println!("No way to get there");
+            }
+        }
+
+        while let Some(waypoint) = click_move.path.first().copied() {
+            let to_waypoint = waypoint - player_mov.position;
+            let distance = to_waypoint.length();
+            if distance < WAYPOINT_REACHED {
+                click_move.path.remove(0);
+                continue;
+            }
+            // full speed through the turns, slowing down only for the last one
+            let throttle = if click_move.path.len() == 1 {
+                (distance / ARRIVE_RADIUS).min(1.)
+            } else {
+                1.
+            };
+            player_locomotion.input = to_waypoint / distance * throttle;
+            break;
+        }
+    }
+}
+
+fn player_aim(
+    cursor_pos: Res<CursorPosition>,
+    mut player_query: Query<
+        (
+            &PlayerIdCmp,
+            &RectangleHitboxCmp,
+            &LocomotionCmp,
+            &mut AimCmp,
+            &Children,
+        ),
+        With<PlayerEntity>,
+    >,
+    mut indicator_query: Query<&mut Transform, With<AimIndicatorEntity>>,
+) {
+    for (player_id, player_hitbox, player_locomotion, mut aim, children) in player_query.iter_mut()
+    {
+        let target = match (player_id.0, cursor_pos.x, cursor_pos.y) {
+            (MOUSE_PLAYER, Some(x), Some(y)) => Vec2::new(x, y) - player_hitbox.rect.center().0,
+            _ => player_locomotion.input,
+        };
+        if target != Vec2::ZERO {
+            aim.direction = target.normalize();
+        }
+        for child in children.iter() {
+            if let Ok(mut indicator_tf) = indicator_query.get_mut(*child) {
+                // y points up in bevy
+                indicator_tf.translation.x = aim.direction.x * AIM_INDICATOR_DISTANCE;
+                indicator_tf.translation.y = -aim.direction.y * AIM_INDICATOR_DISTANCE;
+            }
+        }
+    }
+}
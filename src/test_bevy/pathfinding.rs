@@ -0,0 +1,276 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use bevy::prelude::*;
+
+use crate::test_bevy::*;
+
+// Costs of a straight and a diagonal step, scaled so they stay integers
+const STRAIGHT_COST: u32 = 10;
+const DIAGONAL_COST: u32 = 14;
+
+// Grid of (col, row) cells an entity can't enter, built from the wall hitboxes so open doors
+// and destroyed walls are taken into account
+pub fn blocked_grid<'a, I>(map: &Map, walls: I) -> Vec<Vec<bool>>
+where
+    I: IntoIterator<Item = &'a collision::Rect>,
+{
+    let (width, height) = map.map_size();
+    let mut blocked = vec![vec![false; width]; height];
+    let block_size = map.block_size();
+    for rect in walls {
+        let from = (rect.upper_left.0 - map.topleft()) / block_size;
+        let to = (rect.lower_right.0 - map.topleft()) / block_size;
+        let cols = (from.x.floor().max(0.) as usize)..(to.x.ceil().max(0.) as usize).min(width);
+        let rows = (from.y.floor().max(0.) as usize)..(to.y.ceil().max(0.) as usize).min(height);
+        for row in rows {
+            for col in cols.clone() {
+                blocked[row][col] = true;
+            }
+        }
+    }
+    blocked
+}
+
+// How many cells an entity of this size covers on each side, at least one
+pub fn footprint_cells(size: Vec2, block_size: Vec2) -> usize {
+    (size.x / block_size.x)
+        .max(size.y / block_size.y)
+        .ceil()
+        .max(1.) as usize
+}
+
+// Cells where an entity footprint x footprint cells big fits with its top-left corner there
+pub fn clearance_grid(blocked: &[Vec<bool>], footprint: usize) -> Vec<Vec<bool>> {
+    let height = blocked.len();
+    let width = blocked.first().map_or(0, |row| row.len());
+    let mut walkable = vec![vec![false; width]; height];
+    for (row, walkable_row) in walkable.iter_mut().enumerate() {
+        for (col, cell) in walkable_row.iter_mut().enumerate() {
+            *cell = row + footprint <= height
+                && col + footprint <= width
+                && blocked[row..row + footprint]
+                    .iter()
+                    .all(|blocked_row| blocked_row[col..col + footprint].iter().all(|b| !b));
+        }
+    }
+    walkable
+}
+
+fn octile_distance(from: (usize, usize), to: (usize, usize)) -> u32 {
+    let dx = (from.0 as i64 - to.0 as i64).unsigned_abs() as u32;
+    let dy = (from.1 as i64 - to.1 as i64).unsigned_abs() as u32;
+    STRAIGHT_COST * dx.max(dy) + (DIAGONAL_COST - STRAIGHT_COST) * dx.min(dy)
+}
+
+// The walkable cell closest to cell, the cell itself if it is walkable
+pub fn nearest_walkable(walkable: &[Vec<bool>], cell: (usize, usize)) -> Option<(usize, usize)> {
+    let mut nearest: Option<((usize, usize), u32)> = None;
+    for (row, cells) in walkable.iter().enumerate() {
+        for (col, walkable) in cells.iter().enumerate() {
+            if !*walkable {
+                continue;
+            }
+            let distance = octile_distance((col, row), cell);
+            if nearest.is_none_or(|(_, nearest_distance)| distance < nearest_distance) {
+                nearest = Some(((col, row), distance));
+            }
+        }
+    }
+    nearest.map(|(cell, _)| cell)
+}
+
+// A* over the walkable cells with 8 neighbors. Diagonal steps may not cut a corner, so the path
+// never squeezes between two walls touching at a corner. Returns the cells from start to goal.
+pub fn find_grid_path(
+    walkable: &[Vec<bool>],
+    start: (usize, usize),
+    goal: (usize, usize),
+) -> Option<Vec<(usize, usize)>> {
+    let height = walkable.len();
+    let width = walkable.first().map_or(0, |row| row.len());
+    let is_walkable = |col: i64, row: i64| {
+        col >= 0
+            && row >= 0
+            && (col as usize) < width
+            && (row as usize) < height
+            && walkable[row as usize][col as usize]
+    };
+    if !is_walkable(start.0 as i64, start.1 as i64) || !is_walkable(goal.0 as i64, goal.1 as i64) {
+        return None;
+    }
+
+    let idx = |cell: (usize, usize)| cell.1 * width + cell.0;
+    let mut cost = vec![u32::MAX; width * height];
+    let mut came_from: Vec<Option<(usize, usize)>> = vec![None; width * height];
+    let mut open = BinaryHeap::new();
+    cost[idx(start)] = 0;
+    open.push(Reverse((octile_distance(start, goal), start)));
+
+    while let Some(Reverse((_, cell))) = open.pop() {
+        if cell == goal {
+            let mut path = vec![goal];
+            let mut current = goal;
+            while let Some(previous) = came_from[idx(current)] {
+                path.push(previous);
+                current = previous;
+            }
+            path.reverse();
+            return Some(path);
+        }
+        for dy in -1_i64..=1 {
+            for dx in -1_i64..=1 {
+                if dx == 0 && dy == 0 {
+                    continue;
+                }
+                let (col, row) = (cell.0 as i64 + dx, cell.1 as i64 + dy);
+                if !is_walkable(col, row) {
+                    continue;
+                }
+                let diagonal = dx != 0 && dy != 0;
+                if diagonal
+                    && !(is_walkable(cell.0 as i64 + dx, cell.1 as i64)
+                        && is_walkable(cell.0 as i64, cell.1 as i64 + dy))
+                {
+                    continue;
+                }
+                let next = (col as usize, row as usize);
+                let step = if diagonal {
+                    DIAGONAL_COST
+                } else {
+                    STRAIGHT_COST
+                };
+                let next_cost = cost[idx(cell)] + step;
+                if next_cost < cost[idx(next)] {
+                    cost[idx(next)] = next_cost;
+                    came_from[idx(next)] = Some(cell);
+                    open.push(Reverse((next_cost + octile_distance(next, goal), next)));
+                }
+            }
+        }
+    }
+    None
+}
+
+// Drops the cells in the middle of straight runs, only the turns are left
+pub fn simplify_grid_path(path: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    let step = |from: (usize, usize), to: (usize, usize)| {
+        (to.0 as i64 - from.0 as i64, to.1 as i64 - from.1 as i64)
+    };
+    let mut simplified = Vec::new();
+    for (i, cell) in path.iter().enumerate() {
+        let turn =
+            i == 0 || i + 1 == path.len() || step(path[i - 1], *cell) != step(*cell, path[i + 1]);
+        if turn {
+            simplified.push(*cell);
+        }
+    }
+    simplified
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // '*' is a wall, anything else is free
+    fn blocked(rows: &[&str]) -> Vec<Vec<bool>> {
+        rows.iter()
+            .map(|row| row.chars().map(|char| char == '*').collect())
+            .collect()
+    }
+
+    fn walkable(rows: &[&str]) -> Vec<Vec<bool>> {
+        clearance_grid(&blocked(rows), 1)
+    }
+
+    #[test]
+    fn no_corner_cutting() {
+        // the only diagonal step squeezes between two walls touching at a corner
+        let grid = walkable(&[
+            "  *", //
+            " * ", //
+            "*  ",
+        ]);
+        assert_eq!(find_grid_path(&grid, (0, 0), (2, 2)), None);
+
+        let grid = walkable(&[
+            "   ", //
+            "*  ", //
+            "   ",
+        ]);
+        let path = find_grid_path(&grid, (0, 0), (0, 2)).unwrap();
+        for step in path.windows(2) {
+            let (from, to) = (step[0], step[1]);
+            if from.0 != to.0 && from.1 != to.1 {
+                assert!(grid[from.1][to.0] && grid[to.1][from.0], "{:?}", path);
+            }
+        }
+        assert_eq!(path.first(), Some(&(0, 0)));
+        assert_eq!(path.last(), Some(&(0, 2)));
+    }
+
+    #[test]
+    fn footprint_needs_the_whole_block_free() {
+        let grid = clearance_grid(
+            &blocked(&[
+                "    ", //
+                "  * ", //
+                "    ",
+            ]),
+            2,
+        );
+        assert_eq!(
+            grid,
+            vec![
+                vec![true, false, false, false],
+                vec![true, false, false, false],
+                vec![false, false, false, false],
+            ]
+        );
+        assert_eq!(footprint_cells(Vec2::new(35., 10.), Vec2::new(20., 20.)), 2);
+        assert_eq!(footprint_cells(Vec2::new(5., 5.), Vec2::new(20., 20.)), 1);
+    }
+
+    #[test]
+    fn wide_entities_avoid_narrow_gaps() {
+        let rows = [
+            "     ", //
+            "** **", //
+            "     ", //
+            "     ",
+        ];
+        assert!(find_grid_path(&walkable(&rows), (0, 0), (0, 2)).is_some());
+        assert_eq!(
+            find_grid_path(&clearance_grid(&blocked(&rows), 2), (0, 2), (3, 2)).map(|p| p.len()),
+            Some(4)
+        );
+        assert_eq!(
+            find_grid_path(&clearance_grid(&blocked(&rows), 2), (0, 0), (0, 2)),
+            None
+        );
+    }
+
+    #[test]
+    fn unreachable_goal() {
+        let grid = walkable(&[
+            "  *  ", //
+            "  *  ", //
+            "  *  ",
+        ]);
+        assert_eq!(find_grid_path(&grid, (0, 0), (4, 2)), None);
+        // a goal inside a wall
+        assert_eq!(find_grid_path(&grid, (0, 0), (2, 1)), None);
+        assert_eq!(nearest_walkable(&grid, (2, 1)).map(|(_, row)| row), Some(1));
+    }
+
+    #[test]
+    fn simplify_keeps_only_the_turns() {
+        let path = [(0, 0), (1, 0), (2, 0), (3, 1), (4, 2), (4, 3), (4, 4)];
+        assert_eq!(
+            simplify_grid_path(&path),
+            vec![(0, 0), (2, 0), (4, 2), (4, 4)]
+        );
+        assert_eq!(simplify_grid_path(&[(1, 1)]), vec![(1, 1)]);
+        assert_eq!(simplify_grid_path(&[]), Vec::<(usize, usize)>::new());
+    }
+}
@@ -8,109 +8,192 @@ pub struct PlayerPlugin;
 
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_startup_stage(
-            "game_setup_player",
-            SystemStage::single(player_spawn.system()),
+        app.add_system_set(
+            SystemSet::on_update(AppState::Playing)
+                .with_system(player_spawn.system().after("map_spawn_system"))
+                .with_system(player_movement.system().label("player_movement_system"))
+                .with_system(
+                    player_collision
+                        .system()
+                        .label("player_collision_system")
+                        .after("player_movement_system"),
+                )
+                .with_system(
+                    player_update_hitbox
+                        .system()
+                        .label("player_update_hitbox_system")
+                        .after("player_collision_system"),
+                ),
         )
-        .add_system(player_movement.system().label("player_movement_system"))
-        .add_system(
-            player_collision
-                .system()
-                .label("player_collision_system")
-                .after("player_movement_system"),
-        )
-        .add_system(
-            player_update_hitbox
-                .system()
-                .label("player_update_hitbox_system")
-                .after("player_collision_system"),
+        .add_system_set(
+            SystemSet::on_exit(AppState::Playing).with_system(player_teardown.system()),
         );
     }
 }
 
-fn player_spawn(mut commands: Commands, materials: Res<Materials>, win_size: Res<GameWindowSize>) {
-    let (player_w, player_h): (f32, f32) = (35., 35.);
+// How many people play on this machine, set with --players <n>
+pub struct LocalPlayers {
+    pub count: usize,
+}
+
+impl LocalPlayers {
+    pub const MAX: usize = 4;
+
+    pub fn from_args() -> Self {
+        let args: Vec<String> = std::env::args().collect();
+        let count = args
+            .iter()
+            .position(|arg| arg == "--players")
+            .and_then(|idx| args.get(idx + 1))
+            .and_then(|count| count.parse::<usize>().ok())
+            .unwrap_or(1)
+            .clamp(1, Self::MAX);
+        Self { count }
+    }
+}
+
+// Which local player an entity is, picks its bindings, gamepad, color and spawn point
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayerIdCmp(pub usize);
+
+// The n-th spawn point (see Map::player_spawns) for the n-th player, players past the last one
+// share the first
+pub fn player_spawn_point(spawn_points: &[Vec2], player: usize) -> Option<Vec2> {
+    spawn_points
+        .get(player)
+        .or_else(|| spawn_points.first())
+        .copied()
+}
+
+// Spawns every local player on the map's 'P' cells once the map is loaded
+fn player_spawn(
+    mut commands: Commands,
+    materials: Res<Materials>,
+    config: Res<GlobalConfig>,
+    players: Res<LocalPlayers>,
+    spawn_points: Res<PlayerSpawnPoints>,
+    player_query: Query<Entity, With<PlayerEntity>>,
+) {
+    if player_query.iter().next().is_some() {
+        return;
+    }
+    for player in 0..players.count {
+        if let Some(spawn_point) = player_spawn_point(&spawn_points.0, player) {
+            spawn_player(&mut commands, &materials, &config, player, spawn_point);
+        }
+    }
+}
+
+fn player_teardown(mut commands: Commands, player_query: Query<Entity, With<PlayerEntity>>) {
+    for player in player_query.iter() {
+        commands.entity(player).despawn_recursive();
+    }
+}
+
+pub fn spawn_player(
+    commands: &mut Commands,
+    materials: &Res<Materials>,
+    config: &Res<GlobalConfig>,
+    player: usize,
+    spawn_point: Vec2,
+) -> Entity {
+    let (player_w, player_h): (f32, f32) = config.player_hitbox_size.into();
     // sp = spawn point - in the top-left origin coordinate
-    let mut sp = Point::new((100., 100.));
+    let mut sp = Point::new(spawn_point);
     // convert sp to bevy's sp
-    let mut bevy_sp = topleft_to_mid_origin(&sp, &win_size);
+    let mut bevy_sp = world_to_bevy(&sp);
     // since bevy spawns's center matches its sp, we need our sp to be its top-left corner
     bevy_sp = bevy_sp + Point::new((player_w / 2., -player_h / 2.));
 
-    commands
+    let material = &materials.player_materials[player % materials.player_materials.len()];
+    let entity = commands
         .spawn_bundle(SpriteBundle {
-            material: materials.player_materials.clone(),
+            material: material.clone(),
             sprite: Sprite::new(Vec2::new(player_w, player_h)),
             transform: Transform::from_xyz(bevy_sp.x(), bevy_sp.y(), 1.),
             ..Default::default()
         })
         .insert(PlayerEntity)
-        .insert(NameCmp(STR("ItsMe")))
+        .insert(PlayerIdCmp(player))
+        .insert(NameCmp(format!("Player {}", player + 1)))
         .insert(MovementCmp {
             position: sp.0,
-            speed: Vec2::new(800., 800.),
+            speed: Vec2::new(config.player_speed, config.player_speed),
             direction: Vec2::new(0., 0.),
             delta: Vec2::new(0., 0.),
         })
         .insert(RectangleHitboxCmp {
             rect: collision::Rect::new((sp, player_w, player_h)),
-        });
+        })
+        .insert(LocomotionCmp::player(config))
+        .insert(DashCmp::player(config))
+        .insert(WeaponCmp::from_args())
+        .insert(HealthCmp::new(100., 1.))
+        .insert(AimCmp::default())
+        .insert(ClickMoveCmp::default())
+        .with_children(|parent| spawn_aim_indicator(parent, materials))
+        .id();
+
+    println!("Spawned Player {} at ({} -> {})", player + 1, sp, bevy_sp);
+    entity
+}
 
-    println!("Spawned Player at ({} -> {})", sp, bevy_sp);
+// Moves the player to a new top-left position, e.g. the spawn point of the next level
+pub fn place_player(
+    position: Vec2,
+    player_mov: &mut MovementCmp,
+    player_hitbox: &mut RectangleHitboxCmp,
+    player_tf: &mut Transform,
+) {
+    let sp = Point::new(position);
+    let (player_w, player_h) = (player_hitbox.rect.width, player_hitbox.rect.height);
+    let mut bevy_sp = world_to_bevy(&sp);
+    bevy_sp = bevy_sp + Point::new((player_w / 2., -player_h / 2.));
+
+    player_mov.position = position;
+    player_mov.direction = Vec2::ZERO;
+    player_mov.delta = Vec2::ZERO;
+    player_hitbox.rect = collision::Rect::new((sp, player_w, player_h));
+    player_tf.translation.x = bevy_sp.x();
+    player_tf.translation.y = bevy_sp.y();
 }
 
 fn player_movement(
-    keyboard_input: Res<Input<KeyCode>>,
-    mut player_query: Query<(&mut MovementCmp, &mut Transform), With<PlayerEntity>>,
+    action_state: Res<ActionState>,
+    mut player_query: Query<(&PlayerIdCmp, &mut LocomotionCmp, &mut DashCmp), With<PlayerEntity>>,
 ) {
-    if let Ok((mut player_mov, mut player_tf)) = player_query.single_mut() {
-        let up = keyboard_input.pressed(KeyCode::W) as u32;
-        let down = keyboard_input.pressed(KeyCode::S) as u32;
-        let left = keyboard_input.pressed(KeyCode::A) as u32;
-        let right = keyboard_input.pressed(KeyCode::D) as u32;
-
-        let mut direction = match (up << 3) | (down << 2) | (left << 1) | right {
-            8 => Vec2::new(0., -1.),
-            4 => Vec2::new(0., 1.),
-            2 => Vec2::new(-1., 0.),
-            1 => Vec2::new(1., 0.),
-            10 => Vec2::new(-1., -1.),
-            9 => Vec2::new(1., -1.),
-            6 => Vec2::new(-1., 1.),
-            5 => Vec2::new(1., 1.),
-            _ => Vec2::new(0., 0.),
+    for (player_id, mut player_locomotion, mut player_dash) in player_query.iter_mut() {
+        let actions = match action_state.player(player_id.0) {
+            Some(actions) => actions,
+            None => continue,
         };
+        // opposite actions cancel out, sticks give values in between
+        let direction = Vec2::new(
+            actions.value(InputAction::MoveRight) - actions.value(InputAction::MoveLeft),
+            actions.value(InputAction::MoveDown) - actions.value(InputAction::MoveUp),
+        );
 
-        player_mov.direction = direction;
+        // locomotion accelerates towards it
+        player_locomotion.input = direction;
+        player_dash.requested |= actions.just_pressed(InputAction::Dash);
     }
 }
 
 fn player_collision(
     mut commands: Commands,
-    mut materials: ResMut<Assets<ColorMaterial>>,
     windows: Res<Windows>,
     mut player_query: Query<
         (&mut MovementCmp, &RectangleHitboxCmp, &mut Transform),
         With<PlayerEntity>,
     >,
-    mut wall_query: Query<
-        (
-            Entity,
-            &RectangleHitboxCmp,
-            &CollideCmp,
-            &Handle<ColorMaterial>,
-        ),
-        With<WallEntity>,
-    >,
+    wall_query: Query<(Entity, &RectangleHitboxCmp, &CollideCmp), With<WallEntity>>,
 ) {
-    if let Ok((mut player_mov, player_hitbox, mut player_tf)) = player_query.single_mut() {
+    for (mut player_mov, player_hitbox, mut player_tf) in player_query.iter_mut() {
         let mut delta = player_mov.direction * player_mov.speed * TIME_STEP;
 
         let mut contact_normals = 0_u32;
 
-        for (wall_entity, wall_hitbox, wall_collide, wall_color) in wall_query.iter_mut() {
-            let color = &mut materials.get_mut(wall_color).unwrap().color;
-
+        for (wall_entity, wall_hitbox, wall_collide) in wall_query.iter() {
             let (collided, contact_point, contact_normal, contact_time) =
                 wall_hitbox.rect.is_rect_collide(
                     &player_hitbox.rect,
@@ -120,8 +203,6 @@ fn player_collision(
                 );
 
             if collided {
-                set_bevy_color_rgba(color, 0., 1., 1., 1.);
-
                 // resolve the collision
                 // Only add to delta unique normals since the moving block can collide with multiple
                 // blocks resulting in duplication of the delta += .. statement.
@@ -140,15 +221,13 @@ fn player_collision(
                     }
                 }
                 contact_normals = contact_normals_new;
-            } else {
-                set_bevy_color_rgba(color, 1., 1., 1., 1.);
             }
         }
         player_mov.delta = delta;
         player_mov.position += delta;
         player_tf.translation.x += delta.x;
         player_tf.translation.y += -delta.y;
-        // reset player's direction vector
+        // reset player's direction vector, locomotion sets it again from the velocity
         player_mov.direction = Vec2::ZERO;
     }
 }
@@ -156,7 +235,7 @@ fn player_collision(
 fn player_update_hitbox(
     mut player_query: Query<(&MovementCmp, &mut RectangleHitboxCmp), With<PlayerEntity>>,
 ) {
-    if let Ok((player_mov, mut player_hitbox)) = player_query.single_mut() {
+    for (player_mov, mut player_hitbox) in player_query.iter_mut() {
         player_hitbox
             .rect
             .r#move(player_mov.delta.x, player_mov.delta.y);
@@ -0,0 +1,517 @@
+use fstrings::*;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::test_bevy::*;
+
+// Generators for levels nobody has to draw by hand. They carve floor out of a grid that starts
+// as solid wall, then drop the spawn points on it and make sure the player can walk to every
+// monster spawn and to the exit. Walkable means the whole hitbox fits (see clearance_grid), a
+// 1 cell gap doesn't count. The result is a regular Map, built from the default legend.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MapGenerator {
+    // rooms in the leaves of a binary space partition, joined by corridors
+    Bsp { min_leaf: usize },
+    // random noise smoothed by a cellular automaton
+    Caves { fill_ratio: f32, iterations: u32 },
+    // a random walk that digs until enough of the map is floor
+    Drunkard { floor_ratio: f32 },
+}
+
+impl MapGenerator {
+    pub fn from_name(name: &str) -> Option<MapGenerator> {
+        match name {
+            "bsp" => Some(MapGenerator::Bsp { min_leaf: 6 }),
+            "caves" => Some(MapGenerator::Caves {
+                fill_ratio: 0.45,
+                iterations: 4,
+            }),
+            "drunkard" => Some(MapGenerator::Drunkard { floor_ratio: 0.4 }),
+            _ => None,
+        }
+    }
+
+    // --generate <bsp|caves|drunkard> on the command line
+    pub fn from_args() -> Option<MapGenerator> {
+        let args: Vec<String> = std::env::args().collect();
+        args.iter()
+            .position(|arg| arg == "--generate")
+            .and_then(|idx| args.get(idx + 1))
+            .and_then(|name| MapGenerator::from_name(name))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MapGenerator::Bsp { .. } => "bsp",
+            MapGenerator::Caves { .. } => "caves",
+            MapGenerator::Drunkard { .. } => "drunkard",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MapGenSettings {
+    pub generator: MapGenerator,
+    // the same seed and settings always give the same map
+    pub seed: u64,
+    // in cells, border included
+    pub size: (usize, usize),
+    pub monster_spawns: usize,
+    // cells the player and monsters cover on each side, see footprint_cells
+    pub footprint: usize,
+    pub block_size: Vec2,
+    pub topleft: Vec2,
+}
+
+// anything smaller has no room for a floor inside the border
+pub const MIN_GENERATED_SIZE: usize = 5;
+
+// floor[row][col]
+type FloorGrid = Vec<Vec<bool>>;
+
+pub fn generate_map(settings: &MapGenSettings) -> Result<Map, MapError> {
+    let mut rng = ChaCha8Rng::seed_from_u64(settings.seed);
+    let footprint = settings.footprint.max(1);
+    // the border plus room for one entity
+    let min_size = MIN_GENERATED_SIZE.max(footprint + 2);
+    let width = settings.size.0.max(min_size);
+    let height = settings.size.1.max(min_size);
+    let mut floor = vec![vec![false; width]; height];
+
+    match settings.generator {
+        MapGenerator::Bsp { min_leaf } => {
+            let interior = GridArea {
+                col: 1,
+                row: 1,
+                width: width - 2,
+                height: height - 2,
+            };
+            generate_bsp(
+                &mut rng,
+                &mut floor,
+                interior,
+                min_leaf.max(3).max(footprint + 2),
+                footprint,
+            );
+        }
+        MapGenerator::Caves {
+            fill_ratio,
+            iterations,
+        } => generate_caves(&mut rng, &mut floor, fill_ratio, iterations),
+        MapGenerator::Drunkard { floor_ratio } => {
+            generate_drunkard(&mut rng, &mut floor, floor_ratio, footprint)
+        }
+    }
+
+    // the spawns go where a whole entity fits, with its top-left corner on the cell
+    let mut floor_cells = clear_cells(&floor, footprint);
+    if floor_cells.is_empty() {
+        let center = ((width - footprint) / 2, (height - footprint) / 2);
+        dig(&mut floor, center.0, center.1, footprint);
+        floor_cells.push(center);
+    }
+
+    // the player anywhere, the exit as far as possible from it and the monsters somewhere in the
+    // half of the floor furthest away
+    let player = floor_cells[rng.gen_range(0..floor_cells.len())];
+    let distance = |(col, row): (usize, usize)| {
+        (col as i64 - player.0 as i64).abs() + (row as i64 - player.1 as i64).abs()
+    };
+    let mut candidates: Vec<(usize, usize)> = floor_cells
+        .iter()
+        .copied()
+        .filter(|cell| *cell != player)
+        .collect();
+    candidates.sort_by_key(|cell| std::cmp::Reverse(distance(*cell)));
+    let exit = if candidates.is_empty() {
+        None
+    } else {
+        Some(candidates.remove(0))
+    };
+    candidates.truncate(candidates.len().div_ceil(2));
+    let mut monsters = Vec::new();
+    while monsters.len() < settings.monster_spawns && !candidates.is_empty() {
+        monsters.push(candidates.swap_remove(rng.gen_range(0..candidates.len())));
+    }
+
+    // dig a corridor as wide as an entity from every monster spawn (and the exit) the player
+    // can't reach. Digging only adds floor, so what was reachable stays reachable.
+    for target in monsters.iter().chain(exit.iter()) {
+        let reachable = reachable_cells(&floor, player, footprint);
+        if !reachable[target.1][target.0] {
+            carve_corridor(&mut rng, &mut floor, *target, player, footprint);
+        }
+    }
+
+    let legend = MapLegend::default();
+    let glyph_of = |kind: TileKind| {
+        legend
+            .0
+            .iter()
+            .find(|(_, legend_kind)| **legend_kind == kind)
+            .map(|(glyph, _)| *glyph)
+            .unwrap()
+    };
+    let mut grid: Vec<Vec<char>> = floor
+        .iter()
+        .map(|floor_row| {
+            floor_row
+                .iter()
+                .map(|is_floor| {
+                    glyph_of(if *is_floor {
+                        TileKind::Empty
+                    } else {
+                        TileKind::Wall
+                    })
+                })
+                .collect()
+        })
+        .collect();
+    grid[player.1][player.0] = glyph_of(TileKind::PlayerSpawn);
+    for monster in monsters.iter() {
+        grid[monster.1][monster.0] = glyph_of(TileKind::MonsterSpawn);
+    }
+    if let Some(exit) = exit {
+        grid[exit.1][exit.0] = glyph_of(TileKind::Exit);
+    }
+
+    let rows: Vec<String> = grid.iter().map(|row| row.iter().collect()).collect();
+    let rows: Vec<&str> = rows.iter().map(|row| row.as_str()).collect();
+    build_map(
+        f!("{} #{}", settings.generator.name(), settings.seed),
+        settings.block_size,
+        settings.topleft,
+        legend.clone(),
+        &rows,
+        1,
+        Vec::new(),
+    )
+}
+
+#[derive(Debug, Clone, Copy)]
+struct GridArea {
+    col: usize,
+    row: usize,
+    width: usize,
+    height: usize,
+}
+
+impl GridArea {
+    fn center(&self) -> (usize, usize) {
+        (self.col + self.width / 2, self.row + self.height / 2)
+    }
+}
+
+// Splits the area along its longer side until the leaves get smaller than min_leaf, puts a room
+// in every leaf and joins the two halves of every split with a corridor. Returns the center of
+// one of the rooms inside the area.
+fn generate_bsp(
+    rng: &mut ChaCha8Rng,
+    floor: &mut FloorGrid,
+    area: GridArea,
+    min_leaf: usize,
+    footprint: usize,
+) -> (usize, usize) {
+    let can_split_cols = area.width >= 2 * min_leaf;
+    let can_split_rows = area.height >= 2 * min_leaf;
+    if !can_split_cols && !can_split_rows {
+        // rooms keep a wall around them when the leaf is big enough
+        let room_size = |len: usize| (len.min(3), len.saturating_sub(2).max(len.min(3)));
+        let (min_width, max_width) = room_size(area.width);
+        let (min_height, max_height) = room_size(area.height);
+        let room_width = rng.gen_range(min_width..=max_width);
+        let room_height = rng.gen_range(min_height..=max_height);
+        let room = GridArea {
+            col: area.col + rng.gen_range(0..=area.width - room_width),
+            row: area.row + rng.gen_range(0..=area.height - room_height),
+            width: room_width,
+            height: room_height,
+        };
+        for floor_row in floor.iter_mut().skip(room.row).take(room.height) {
+            for cell in floor_row.iter_mut().skip(room.col).take(room.width) {
+                *cell = true;
+            }
+        }
+        return room.center();
+    }
+
+    let split_cols = can_split_cols && (!can_split_rows || area.width >= area.height);
+    let (first, second) = if split_cols {
+        let split = rng.gen_range(min_leaf..=area.width - min_leaf);
+        (
+            GridArea {
+                width: split,
+                ..area
+            },
+            GridArea {
+                col: area.col + split,
+                width: area.width - split,
+                ..area
+            },
+        )
+    } else {
+        let split = rng.gen_range(min_leaf..=area.height - min_leaf);
+        (
+            GridArea {
+                height: split,
+                ..area
+            },
+            GridArea {
+                row: area.row + split,
+                height: area.height - split,
+                ..area
+            },
+        )
+    };
+    let first_room = generate_bsp(rng, floor, first, min_leaf, footprint);
+    let second_room = generate_bsp(rng, floor, second, min_leaf, footprint);
+    carve_corridor(rng, floor, first_room, second_room, footprint);
+    if rng.gen_bool(0.5) {
+        first_room
+    } else {
+        second_room
+    }
+}
+
+fn generate_caves(rng: &mut ChaCha8Rng, floor: &mut FloorGrid, fill_ratio: f32, iterations: u32) {
+    let height = floor.len();
+    let width = floor[0].len();
+    for floor_row in floor.iter_mut().take(height - 1).skip(1) {
+        for cell in floor_row.iter_mut().take(width - 1).skip(1) {
+            *cell = rng.gen::<f32>() >= fill_ratio;
+        }
+    }
+    // a cell turns into wall when most of its 3x3 neighborhood is wall, which melts the noise
+    // into smooth caves
+    for _ in 0..iterations {
+        let previous = floor.clone();
+        for (row, floor_row) in floor.iter_mut().enumerate().take(height - 1).skip(1) {
+            for (col, cell) in floor_row.iter_mut().enumerate().take(width - 1).skip(1) {
+                let walls = previous[row - 1..=row + 1]
+                    .iter()
+                    .flat_map(|previous_row| previous_row[col - 1..=col + 1].iter())
+                    .filter(|is_floor| !**is_floor)
+                    .count();
+                *cell = walls < 5;
+            }
+        }
+    }
+}
+
+fn generate_drunkard(
+    rng: &mut ChaCha8Rng,
+    floor: &mut FloorGrid,
+    floor_ratio: f32,
+    footprint: usize,
+) {
+    let height = floor.len();
+    let width = floor[0].len();
+    let interior = (width - 2) * (height - 2);
+    let target = ((interior as f32 * floor_ratio) as usize).clamp(1, interior);
+    let (mut col, mut row) = (width / 2, height / 2);
+    let mut dug = 0;
+    // the walk can get stuck going over old floor, give up eventually
+    for _ in 0..interior * 50 {
+        dug += dig(floor, col, row, footprint);
+        if dug >= target {
+            break;
+        }
+        match rng.gen_range(0..4) {
+            0 if col > 1 => col -= 1,
+            1 if col < width - 2 => col += 1,
+            2 if row > 1 => row -= 1,
+            3 if row < height - 2 => row += 1,
+            _ => {}
+        }
+    }
+}
+
+// Digs out the size x size block with its top-left corner at (col, row), so the paths are wide
+// enough for the hitboxes of the player and monsters. The border is never touched. Returns how
+// many cells turned into floor.
+fn dig(floor: &mut FloorGrid, col: usize, row: usize, size: usize) -> usize {
+    let height = floor.len();
+    let width = floor[0].len();
+    let mut dug = 0;
+    let (rows, cols) = (
+        row.max(1)..(row + size).min(height - 1),
+        col.max(1)..(col + size).min(width - 1),
+    );
+    for floor_row in floor.iter_mut().take(rows.end).skip(rows.start) {
+        for cell in floor_row.iter_mut().take(cols.end).skip(cols.start) {
+            if !*cell {
+                *cell = true;
+                dug += 1;
+            }
+        }
+    }
+    dug
+}
+
+// Digs an L-shaped corridor between two cells, bending at a random corner
+fn carve_corridor(
+    rng: &mut ChaCha8Rng,
+    floor: &mut FloorGrid,
+    from: (usize, usize),
+    to: (usize, usize),
+    width: usize,
+) {
+    let corner = if rng.gen_bool(0.5) {
+        (to.0, from.1)
+    } else {
+        (from.0, to.1)
+    };
+    for (start, end) in [(from, corner), (corner, to)].iter() {
+        for row in start.1.min(end.1)..=start.1.max(end.1) {
+            for col in start.0.min(end.0)..=start.0.max(end.0) {
+                dig(floor, col, row, width);
+            }
+        }
+    }
+}
+
+fn walls_of(floor: &[Vec<bool>]) -> Vec<Vec<bool>> {
+    floor
+        .iter()
+        .map(|floor_row| floor_row.iter().map(|is_floor| !is_floor).collect())
+        .collect()
+}
+
+// (col, row) of every cell a footprint x footprint entity fits on, row by row
+fn clear_cells(floor: &[Vec<bool>], footprint: usize) -> Vec<(usize, usize)> {
+    let mut cells = Vec::new();
+    for (row, clear_row) in clearance_grid(&walls_of(floor), footprint)
+        .iter()
+        .enumerate()
+    {
+        for (col, is_clear) in clear_row.iter().enumerate() {
+            if *is_clear {
+                cells.push((col, row));
+            }
+        }
+    }
+    cells
+}
+
+// Flood fill, 4-connected, over the cells a footprint x footprint entity fits on. Two neighbors
+// that both fit leave no gap in between, so the entity can slide from one to the other.
+fn reachable_cells(floor: &[Vec<bool>], start: (usize, usize), footprint: usize) -> Vec<Vec<bool>> {
+    let clear = clearance_grid(&walls_of(floor), footprint);
+    let mut reached = vec![vec![false; floor[0].len()]; floor.len()];
+    if !clear[start.1][start.0] {
+        return reached;
+    }
+    let mut queue = VecDeque::new();
+    reached[start.1][start.0] = true;
+    queue.push_back(start);
+    while let Some((col, row)) = queue.pop_front() {
+        // the border is never clear, so the neighbors of a clear cell are all inside the grid
+        let neighbors = [
+            (col - 1, row),
+            (col + 1, row),
+            (col, row - 1),
+            (col, row + 1),
+        ];
+        for (next_col, next_row) in neighbors.iter().copied() {
+            if clear[next_row][next_col] && !reached[next_row][next_col] {
+                reached[next_row][next_col] = true;
+                queue.push_back((next_col, next_row));
+            }
+        }
+    }
+    reached
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate(generator: MapGenerator, seed: u64, footprint: usize) -> Map {
+        generate_map(&MapGenSettings {
+            generator,
+            seed,
+            size: (48, 48),
+            monster_spawns: 4,
+            footprint,
+            block_size: Vec2::new(20., 20.),
+            topleft: Vec2::new(40., 40.),
+        })
+        .unwrap()
+    }
+
+    fn cells_of(tiles: &[Vec<Option<TileKind>>], kind: TileKind) -> Vec<(usize, usize)> {
+        let mut cells = Vec::new();
+        for (row, tile_row) in tiles.iter().enumerate() {
+            for (col, tile) in tile_row.iter().enumerate() {
+                if *tile == Some(kind) {
+                    cells.push((col, row));
+                }
+            }
+        }
+        cells
+    }
+
+    #[test]
+    fn spawns_and_exit_are_reachable() {
+        for name in ["bsp", "caves", "drunkard"].iter() {
+            let generator = MapGenerator::from_name(name).unwrap();
+            for seed in 0..20 {
+                for footprint in 1..=2 {
+                    let tiles = generate(generator, seed, footprint).tile_grid();
+                    let floor: FloorGrid = tiles
+                        .iter()
+                        .map(|row| {
+                            row.iter()
+                                .map(|tile| *tile != Some(TileKind::Wall))
+                                .collect()
+                        })
+                        .collect();
+                    let players = cells_of(&tiles, TileKind::PlayerSpawn);
+                    assert_eq!(players.len(), 1, "{} #{}", name, seed);
+                    let reachable = reachable_cells(&floor, players[0], footprint);
+                    let monsters = cells_of(&tiles, TileKind::MonsterSpawn);
+                    assert_eq!(monsters.len(), 4, "{} #{}", name, seed);
+                    let exits = cells_of(&tiles, TileKind::Exit);
+                    assert_eq!(exits.len(), 1, "{} #{}", name, seed);
+                    for (col, row) in players.iter().chain(monsters.iter()).chain(exits.iter()) {
+                        assert!(
+                            reachable[*row][*col],
+                            "{} #{} footprint {}: ({}, {}) can't be reached",
+                            name, seed, footprint, col, row
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn same_seed_same_map() {
+        let generator = MapGenerator::from_name("caves").unwrap();
+        assert_eq!(
+            map_to_string(&generate(generator, 7, 2)),
+            map_to_string(&generate(generator, 7, 2))
+        );
+    }
+
+    #[test]
+    fn tiny_maps_still_get_a_floor() {
+        let map = generate_map(&MapGenSettings {
+            generator: MapGenerator::Caves {
+                fill_ratio: 1.,
+                iterations: 0,
+            },
+            seed: 1,
+            size: (3, 3),
+            monster_spawns: 4,
+            footprint: 3,
+            block_size: Vec2::new(20., 20.),
+            topleft: Vec2::new(40., 40.),
+        })
+        .unwrap();
+        assert_eq!(cells_of(&map.tile_grid(), TileKind::PlayerSpawn).len(), 1);
+    }
+}
@@ -0,0 +1,399 @@
+use bevy::input::*;
+use bevy::prelude::*;
+use bevy::window::*;
+
+use crate::test_bevy::*;
+
+pub struct ProjectilePlugin;
+
+impl Plugin for ProjectilePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_set(
+            SystemSet::on_update(AppState::Playing)
+                .with_system(
+                    player_fire
+                        .system()
+                        .label("player_fire_system")
+                        .after("input_actions_system")
+                        .after("player_update_hitbox_system"),
+                )
+                .with_system(
+                    projectile_move
+                        .system()
+                        .label("projectile_move_system")
+                        .after("player_fire_system")
+                        .after("monster_update_hitbox_system"),
+                )
+                .with_system(tracer_fade.system()),
+        )
+        .add_system_set(
+            SystemSet::on_exit(AppState::Playing).with_system(projectile_teardown.system()),
+        );
+    }
+}
+
+// What the player shoots with, everything can be tuned per entity
+pub struct WeaponCmp {
+    // shots per second while Fire is held
+    pub fire_rate: f32,
+    // px/s
+    pub projectile_speed: f32,
+    // seconds before a projectile that hit nothing disappears
+    pub projectile_lifetime: f32,
+    pub projectile_size: f32,
+    pub damage: f32,
+    // hit instantly along a ray instead of firing a projectile
+    pub hitscan: bool,
+    pub hitscan_range: f32,
+    cooldown_left: f32,
+}
+
+impl WeaponCmp {
+    pub fn blaster() -> Self {
+        Self {
+            fire_rate: 6.,
+            projectile_speed: 900.,
+            projectile_lifetime: 1.5,
+            projectile_size: 6.,
+            damage: 20.,
+            hitscan: false,
+            hitscan_range: 0.,
+            cooldown_left: 0.,
+        }
+    }
+
+    pub fn rail() -> Self {
+        Self {
+            fire_rate: 1.5,
+            damage: 60.,
+            hitscan: true,
+            hitscan_range: 800.,
+            ..Self::blaster()
+        }
+    }
+
+    // the rail gun with --hitscan, the blaster otherwise
+    pub fn from_args() -> Self {
+        if std::env::args().any(|arg| arg == "--hitscan") {
+            Self::rail()
+        } else {
+            Self::blaster()
+        }
+    }
+
+    pub fn cooldown_left(&self) -> f32 {
+        self.cooldown_left
+    }
+
+    pub fn set_cooldown_left(&mut self, cooldown_left: f32) {
+        self.cooldown_left = cooldown_left;
+    }
+}
+
+pub struct ProjectileEntity;
+
+pub struct ProjectileCmp {
+    // px/s, in world coordinates
+    pub velocity: Vec2,
+    pub damage: f32,
+    pub lifetime: Timer,
+}
+
+// The line a hitscan shot leaves behind for a moment
+pub struct TracerCmp {
+    pub timer: Timer,
+}
+
+// Where a moving rect first touches one of the rects, as (index, contact time, normal)
+fn first_contact<'a, I>(
+    moving: &collision::Rect,
+    delta: Vec2,
+    rects: I,
+) -> Option<(usize, f32, RectCollisionSide)>
+where
+    I: IntoIterator<Item = &'a collision::Rect>,
+{
+    let mut first: Option<(usize, f32, RectCollisionSide)> = None;
+    for (idx, rect) in rects.into_iter().enumerate() {
+        // already inside counts as touching it right away
+        let contact = if rect.is_rect_overlap(moving) {
+            Some((0., RectCollisionSide::None))
+        } else {
+            match rect.is_rect_collide(moving, &delta, &Vec2::ONE, 1.) {
+                (true, _, Some(normal), Some(time)) => Some((time, normal)),
+                _ => None,
+            }
+        };
+        if let Some((time, normal)) = contact {
+            if first.is_none_or(|(_, first_time, _)| time < first_time) {
+                first = Some((idx, time, normal));
+            }
+        }
+    }
+    first
+}
+
+// Nearest rect the ray passes through, as (index, contact time along the ray)
+fn first_ray_hit<'a, I>(ray: &collision::Ray, rects: I) -> Option<(usize, f32)>
+where
+    I: IntoIterator<Item = &'a collision::Rect>,
+{
+    let mut first: Option<(usize, f32)> = None;
+    for (idx, rect) in rects.into_iter().enumerate() {
+        if let (true, _, _, Some(time)) = rect.is_ray_intersect(ray) {
+            if (0. ..=1.).contains(&time) && first.is_none_or(|(_, first_time)| time < first_time) {
+                first = Some((idx, time));
+            }
+        }
+    }
+    first
+}
+
+fn spawn_projectile(
+    commands: &mut Commands,
+    materials: &Res<Materials>,
+    center: Point,
+    direction: Vec2,
+    weapon: &WeaponCmp,
+) {
+    spawn_projectile_entity(
+        commands,
+        materials,
+        center,
+        weapon.projectile_size,
+        ProjectileCmp {
+            velocity: direction * weapon.projectile_speed,
+            damage: weapon.damage,
+            lifetime: Timer::from_seconds(weapon.projectile_lifetime, false),
+        },
+    );
+}
+
+// A size x size projectile centered on center, also how a saved one comes back
+pub fn spawn_projectile_entity(
+    commands: &mut Commands,
+    materials: &Res<Materials>,
+    center: Point,
+    size: f32,
+    projectile: ProjectileCmp,
+) -> Entity {
+    let sp = center - Vec2::new(size / 2., size / 2.);
+    let bevy_sp = world_to_bevy(&center);
+    commands
+        .spawn_bundle(SpriteBundle {
+            material: materials.projectile_materials.clone(),
+            sprite: Sprite::new(Vec2::new(size, size)),
+            transform: Transform::from_xyz(bevy_sp.x(), bevy_sp.y(), 2.),
+            ..Default::default()
+        })
+        .insert(ProjectileEntity)
+        .insert(NameCmp(STR("Projectile")))
+        .insert(projectile)
+        .insert(RectangleHitboxCmp {
+            rect: collision::Rect::new((sp, size, size)),
+        })
+        .id()
+}
+
+pub fn spawn_tracer(
+    commands: &mut Commands,
+    materials: &Res<Materials>,
+    ray: collision::Ray,
+) -> Entity {
+    let thickness = 2.;
+    let length = ray.start.distance(&ray.end);
+    let mid = world_to_bevy(&Point::new((ray.start.0 + ray.end.0) / 2.));
+    // y flips on the way to bevy, so does the angle
+    let along = ray.end.0 - ray.start.0;
+    let angle = (-along.y).atan2(along.x);
+    let mut transform = Transform::from_xyz(mid.x(), mid.y(), 2.);
+    transform.rotation = Quat::from_rotation_z(angle);
+    commands
+        .spawn_bundle(SpriteBundle {
+            material: materials.ray_materials.clone(),
+            sprite: Sprite::new(Vec2::new(length, thickness)),
+            transform,
+            ..Default::default()
+        })
+        .insert(RayEntity)
+        .insert(NameCmp(STR("Tracer")))
+        .insert(RayCmp {
+            ray,
+            thickness,
+            length,
+        })
+        .insert(TracerCmp {
+            timer: Timer::from_seconds(0.08, false),
+        })
+        .id()
+}
+
+fn player_fire(
+    mut commands: Commands,
+    materials: Res<Materials>,
+    action_state: Res<ActionState>,
+    editor: Res<MapEditor>,
+    mut player_query: Query<
+        (&PlayerIdCmp, &RectangleHitboxCmp, &AimCmp, &mut WeaponCmp),
+        With<PlayerEntity>,
+    >,
+    wall_query: Query<(&RectangleHitboxCmp, &CollideCmp), With<WallEntity>>,
+    monster_query: Query<(Entity, &RectangleHitboxCmp), With<MonsterEntity>>,
+    mut damage_events: EventWriter<DamageEvent>,
+) {
+    for (player_id, player_hitbox, aim, mut weapon) in player_query.iter_mut() {
+        weapon.cooldown_left = (weapon.cooldown_left - TIME_STEP).max(0.);
+        // the click paints a tile while the map editor is open
+        let fire = !editor.active
+            && action_state
+                .player(player_id.0)
+                .is_some_and(|actions| actions.pressed(InputAction::Fire));
+        if !fire || weapon.cooldown_left > 0. {
+            continue;
+        }
+        let center = player_hitbox.rect.center();
+        let direction = aim.direction;
+        weapon.cooldown_left = 1. / weapon.fire_rate;
+
+        if !weapon.hitscan {
+            spawn_projectile(&mut commands, &materials, center, direction, &weapon);
+            continue;
+        }
+
+        // hitscan: the nearest solid wall cuts the ray short, a monster before it takes the hit
+        let mut ray = collision::Ray {
+            start: center,
+            end: center + direction * weapon.hitscan_range,
+        };
+        let walls: Vec<collision::Rect> = wall_query
+            .iter()
+            .filter(|(_, wall_collide)| wall_collide.property != CollideType::None as u32)
+            .map(|(wall_hitbox, _)| wall_hitbox.rect)
+            .collect();
+        if let Some((_, time)) = first_ray_hit(&ray, walls.iter()) {
+            ray.end = center + direction * weapon.hitscan_range * time;
+        }
+        let monsters: Vec<(Entity, collision::Rect)> = monster_query
+            .iter()
+            .map(|(monster, monster_hitbox)| (monster, monster_hitbox.rect))
+            .collect();
+        if let Some((idx, time)) = first_ray_hit(&ray, monsters.iter().map(|(_, rect)| rect)) {
+            ray.end = ray.start + (ray.end.0 - ray.start.0) * time;
+            damage_events.send(DamageEvent {
+                target: monsters[idx].0,
+                amount: weapon.damage,
+            });
+        }
+        spawn_tracer(&mut commands, &materials, ray);
+    }
+}
+
+// Swept against walls and monsters so fast projectiles can't tunnel through anything. Stop and
+// destroy walls swallow the projectile, a wall that only reflects bounces it off.
+fn projectile_move(
+    mut commands: Commands,
+    mut projectile_query: Query<
+        (
+            Entity,
+            &mut ProjectileCmp,
+            &mut RectangleHitboxCmp,
+            &mut Transform,
+        ),
+        With<ProjectileEntity>,
+    >,
+    wall_query: Query<
+        (Entity, &RectangleHitboxCmp, &CollideCmp),
+        (With<WallEntity>, Without<ProjectileEntity>),
+    >,
+    monster_query: Query<
+        (Entity, &RectangleHitboxCmp),
+        (With<MonsterEntity>, Without<ProjectileEntity>),
+    >,
+    mut damage_events: EventWriter<DamageEvent>,
+) {
+    let walls: Vec<(Entity, collision::Rect, u32)> = wall_query
+        .iter()
+        .filter(|(_, _, wall_collide)| wall_collide.property != CollideType::None as u32)
+        .map(|(wall, wall_hitbox, wall_collide)| (wall, wall_hitbox.rect, wall_collide.property))
+        .collect();
+    let monsters: Vec<(Entity, collision::Rect)> = monster_query
+        .iter()
+        .map(|(monster, monster_hitbox)| (monster, monster_hitbox.rect))
+        .collect();
+
+    for (projectile, mut projectile_cmp, mut projectile_hitbox, mut projectile_tf) in
+        projectile_query.iter_mut()
+    {
+        projectile_cmp.lifetime.tick(tick_duration());
+        if projectile_cmp.lifetime.finished() {
+            commands.entity(projectile).despawn();
+            continue;
+        }
+
+        let delta = projectile_cmp.velocity * TIME_STEP;
+        let rect = projectile_hitbox.rect;
+        let wall_hit = first_contact(&rect, delta, walls.iter().map(|(_, rect, _)| rect));
+        let monster_hit = first_contact(&rect, delta, monsters.iter().map(|(_, rect)| rect));
+
+        // a monster in front of the wall takes the hit
+        if let Some((idx, monster_time, _)) = monster_hit {
+            if wall_hit.is_none_or(|(_, wall_time, _)| monster_time <= wall_time) {
+                damage_events.send(DamageEvent {
+                    target: monsters[idx].0,
+                    amount: projectile_cmp.damage,
+                });
+                commands.entity(projectile).despawn();
+                continue;
+            }
+        }
+
+        let mut moved = delta;
+        if let Some((idx, wall_time, normal)) = wall_hit {
+            let (wall, _, property) = walls[idx];
+            let has = |collide_type: CollideType| property & collide_type as u32 != 0;
+            if has(CollideType::Destroy) {
+                damage_events.send(DamageEvent {
+                    target: wall,
+                    amount: projectile_cmp.damage,
+                });
+                commands.entity(projectile).despawn();
+                continue;
+            }
+            if has(CollideType::Stop) || !has(CollideType::Reflect) {
+                commands.entity(projectile).despawn();
+                continue;
+            }
+            // flip the velocity on the axes the normal points along, then travel the rest of
+            // the frame the other way
+            let normal = rect_collision_side_to_vec2(normal);
+            let flip = Vec2::new(
+                if normal.x != 0. { -1. } else { 1. },
+                if normal.y != 0. { -1. } else { 1. },
+            );
+            projectile_cmp.velocity *= flip;
+            moved = delta * wall_time + delta * flip * (1. - wall_time);
+        }
+
+        projectile_hitbox.rect.r#move(moved.x, moved.y);
+        projectile_tf.translation.x += moved.x;
+        projectile_tf.translation.y += -moved.y;
+    }
+}
+
+fn tracer_fade(mut commands: Commands, mut tracer_query: Query<(Entity, &mut TracerCmp)>) {
+    for (tracer, mut tracer_cmp) in tracer_query.iter_mut() {
+        tracer_cmp.timer.tick(tick_duration());
+        if tracer_cmp.timer.finished() {
+            commands.entity(tracer).despawn();
+        }
+    }
+}
+
+fn projectile_teardown(
+    mut commands: Commands,
+    projectile_query: Query<Entity, Or<(With<ProjectileEntity>, With<TracerCmp>)>>,
+) {
+    for projectile in projectile_query.iter() {
+        commands.entity(projectile).despawn();
+    }
+}
@@ -0,0 +1,326 @@
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+use bevy::ecs::schedule::ShouldRun;
+use bevy::input::*;
+use bevy::prelude::*;
+use bevy::window::*;
+
+use crate::test_bevy::*;
+
+// --record <file> writes the actions of every tick to the file, --replay <file> plays them back
+// through the same systems instead of the keyboard, mouse and gamepads. The seed, the number of
+// players and the generator come from the replay, other flags (--hitscan) have to be passed
+// again. The ticks are counted from the first map on, how long the menu and the loading took
+// isn't part of the replay: it starts right in the game and plays the first recorded tick after
+// the first map is spawned. The config and the maps aren't hot reloaded while replaying. While
+// replaying P pauses, . steps one tick and F toggles fast forward.
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        if let Some(path) = replay_arg("--replay") {
+            match ReplayPlayback::load(&path) {
+                Ok(playback) => {
+                    let header = &playback.header;
+                    println!(
+                        "Replaying {}: seed {}, {} player(s), {} ticks",
+                        path,
+                        header.seed,
+                        header.players,
+                        playback.frames.len()
+                    );
+                    let generator = header
+                        .generator
+                        .as_deref()
+                        .and_then(MapGenerator::from_name);
+                    let level = match generator {
+                        Some(generator) => {
+                            LevelManager::new(vec![LevelSource::Generated(generator)], true)
+                        }
+                        None => LevelManager::campaign(),
+                    };
+                    app.insert_resource(GameRng::new(header.seed))
+                        .insert_resource(LocalPlayers {
+                            count: header.players.clamp(1, LocalPlayers::MAX),
+                        })
+                        .insert_resource(level)
+                        .insert_resource(playback)
+                        // past the menu, the recording starts after it anyway
+                        .insert_resource(State::new(AppState::Playing))
+                        .add_system_to_stage(CoreStage::PreUpdate, replay_controls.system())
+                        .add_system_to_stage(CoreStage::PostUpdate, replay_start.system())
+                        // the whole game runs tick by tick, as often as the replay says
+                        .stage(CoreStage::Update, |stage: &mut SystemStage| {
+                            stage.set_run_criteria(replay_tick.system())
+                        });
+                    return;
+                }
+                Err(err) => println!("Can't replay {}: {}", path, err),
+            }
+        }
+        if let Some(path) = replay_arg("--record") {
+            app.insert_resource(ReplayRecorder::new(path))
+                .add_startup_system(replay_start_recording.system())
+                // after the tick, when map_spawn has sent its event whatever the system order
+                .add_system_to_stage(
+                    CoreStage::PostUpdate,
+                    replay_record.system().label("replay_record_system"),
+                );
+        }
+    }
+}
+
+pub const REPLAY_VERSION: u32 = 2;
+// ticks per frame while fast forwarding
+const FAST_FORWARD_TICKS: u32 = 4;
+
+fn replay_arg(flag: &str) -> Option<String> {
+    let args: Vec<String> = std::env::args().collect();
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|idx| args.get(idx + 1))
+        .cloned()
+}
+
+// The first line of a replay file, everything needed to start the same session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayHeader {
+    pub version: u32,
+    pub seed: u64,
+    pub players: usize,
+    // the --generate name, the campaign if there is none
+    pub generator: Option<String>,
+}
+
+// One line per tick after the header, from the tick after the first map was spawned
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayFrame {
+    pub players: Vec<PlayerActions>,
+    // world coordinates, for the mouse aim and click to move
+    pub cursor: Option<(f32, f32)>,
+}
+
+// Each tick goes to disk right away, so a crash still leaves everything up to it
+pub struct ReplayRecorder {
+    pub path: String,
+    writer: Option<BufWriter<File>>,
+    // the first map is in, the ticks get recorded from now on
+    started: bool,
+}
+
+impl ReplayRecorder {
+    pub fn new(path: String) -> Self {
+        Self {
+            path,
+            writer: None,
+            started: false,
+        }
+    }
+
+    pub fn start(&mut self, header: &ReplayHeader) -> Result<(), anyhow::Error> {
+        if let Some(dir) = std::path::Path::new(&self.path).parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut writer = BufWriter::new(File::create(&self.path)?);
+        writeln!(writer, "{}", ron::ser::to_string(header)?)?;
+        self.writer = Some(writer);
+        Ok(())
+    }
+
+    pub fn record(&mut self, frame: &ReplayFrame) -> Result<(), anyhow::Error> {
+        if let Some(writer) = self.writer.as_mut() {
+            writeln!(writer, "{}", ron::ser::to_string(frame)?)?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+pub struct ReplayPlayback {
+    pub header: ReplayHeader,
+    pub frames: Vec<ReplayFrame>,
+    pub paused: bool,
+    pub fast_forward: bool,
+    // the first map is in, until then the game runs without the frames
+    started: bool,
+    // one more tick while paused
+    step: bool,
+    // the frame of the tick being played and the one after it
+    current: usize,
+    next: usize,
+    // still to play in this frame
+    ticks_left: u32,
+}
+
+impl ReplayPlayback {
+    pub fn load(path: &str) -> Result<Self, anyhow::Error> {
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        let header: ReplayHeader = match lines.next() {
+            Some(line) => ron::de::from_str(&line?)?,
+            None => anyhow::bail!("the file is empty"),
+        };
+        if header.version != REPLAY_VERSION {
+            anyhow::bail!(
+                "recorded as version {}, this build plays version {}",
+                header.version,
+                REPLAY_VERSION
+            );
+        }
+        let mut frames = Vec::new();
+        for line in lines {
+            // a recording cut short by a crash can end in half a line
+            match ron::de::from_str(&line?) {
+                Ok(frame) => frames.push(frame),
+                Err(err) => {
+                    println!(
+                        "Replay {} ends early at tick {}: {}",
+                        path,
+                        frames.len(),
+                        err
+                    );
+                    break;
+                }
+            }
+        }
+        Ok(Self {
+            header,
+            frames,
+            paused: false,
+            fast_forward: false,
+            started: false,
+            step: false,
+            current: 0,
+            next: 0,
+            ticks_left: 0,
+        })
+    }
+
+    // what the players did in the tick being played, nothing before the first map
+    pub fn frame(&self) -> Option<&ReplayFrame> {
+        if !self.started {
+            return None;
+        }
+        self.frames.get(self.current)
+    }
+
+    pub fn tick(&self) -> usize {
+        self.current
+    }
+}
+
+fn replay_start_recording(
+    mut recorder: ResMut<ReplayRecorder>,
+    game_rng: Res<GameRng>,
+    players: Res<LocalPlayers>,
+    level: Res<LevelManager>,
+) {
+    let generator = match level.levels.first() {
+        Some(LevelSource::Generated(generator)) => Some(STR(generator.name())),
+        _ => None,
+    };
+    let header = ReplayHeader {
+        version: REPLAY_VERSION,
+        seed: game_rng.seed(),
+        players: players.count,
+        generator,
+    };
+    match recorder.start(&header) {
+        Ok(()) => println!("Recording to {}", recorder.path),
+        Err(err) => println!("Can't record to {}: {}", recorder.path, err),
+    }
+}
+
+fn replay_record(
+    mut recorder: ResMut<ReplayRecorder>,
+    action_state: Res<ActionState>,
+    cursor_pos: Res<CursorPosition>,
+    mut map_spawned_events: EventReader<MapSpawnedEvent>,
+) {
+    // the tick the first map came out in isn't recorded, the replay catches up in it
+    if !recorder.started {
+        recorder.started = map_spawned_events.iter().count() > 0;
+        return;
+    }
+    let frame = ReplayFrame {
+        players: action_state.players().to_vec(),
+        cursor: cursor_pos.x.zip(cursor_pos.y),
+    };
+    if let Err(err) = recorder.record(&frame) {
+        println!("Recording to {} stopped: {}", recorder.path, err);
+        recorder.writer = None;
+    }
+}
+
+// The playback counterpart of the recorder waiting for the first map
+fn replay_start(
+    mut playback: ResMut<ReplayPlayback>,
+    mut map_spawned_events: EventReader<MapSpawnedEvent>,
+) {
+    if !playback.started && map_spawned_events.iter().count() > 0 {
+        playback.started = true;
+        println!("Replay started with the first map");
+    }
+}
+
+fn replay_controls(keyboard_input: Res<Input<KeyCode>>, mut playback: ResMut<ReplayPlayback>) {
+    if keyboard_input.just_pressed(KeyCode::P) {
+        playback.paused = !playback.paused;
+        if playback.paused {
+            println!("Replay paused at tick {}", playback.tick());
+        } else {
+            println!("Replay resumed");
+        }
+    }
+    // pauses first if it is running
+    if keyboard_input.just_pressed(KeyCode::Period) {
+        playback.step = playback.paused;
+        playback.paused = true;
+    }
+    if keyboard_input.just_pressed(KeyCode::F) {
+        playback.fast_forward = !playback.fast_forward;
+        println!(
+            "Fast forward {}",
+            if playback.fast_forward { "on" } else { "off" }
+        );
+    }
+}
+
+// Run criteria of the Update stage, moves the replay one frame ahead for every run
+fn replay_tick(mut playback: ResMut<ReplayPlayback>) -> ShouldRun {
+    let playback = &mut *playback;
+    // the menu is skipped and the loading takes as long as it takes
+    if !playback.started {
+        return ShouldRun::Yes;
+    }
+    if playback.ticks_left == 0 {
+        playback.ticks_left = if playback.paused {
+            playback.step as u32
+        } else if playback.fast_forward {
+            FAST_FORWARD_TICKS
+        } else {
+            1
+        };
+        playback.step = false;
+        if playback.ticks_left == 0 {
+            return ShouldRun::No;
+        }
+    }
+    if playback.next >= playback.frames.len() {
+        if !playback.paused {
+            println!("Replay finished after {} ticks", playback.frames.len());
+        }
+        playback.paused = true;
+        playback.ticks_left = 0;
+        return ShouldRun::No;
+    }
+    playback.current = playback.next;
+    playback.next += 1;
+    playback.ticks_left -= 1;
+    if playback.ticks_left > 0 {
+        ShouldRun::YesAndCheckAgain
+    } else {
+        ShouldRun::Yes
+    }
+}
@@ -0,0 +1,95 @@
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::test_bevy::*;
+
+// All gameplay randomness goes through this resource so a run can be reproduced from its seed.
+// Every consumer asks for its own named stream, so adding a new consumer (or changing how many
+// numbers one of them draws) doesn't shift the sequences of the others.
+pub struct GameRng {
+    seed: u64,
+    streams: HashMap<String, ChaCha8Rng>,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            streams: HashMap::new(),
+        }
+    }
+
+    // --seed <u64> on the command line, then the seed of the config, otherwise a fresh seed that
+    // gets printed so the run can be replayed
+    pub fn from_args(config: &GlobalConfig) -> Self {
+        let args: Vec<String> = std::env::args().collect();
+        let seed = args
+            .iter()
+            .position(|arg| arg == "--seed")
+            .and_then(|idx| args.get(idx + 1))
+            .and_then(|seed| seed.parse::<u64>().ok())
+            .or(config.seed)
+            .unwrap_or_else(|| rand::thread_rng().next_u64());
+        println!("RNG seed: {}", seed);
+        Self::new(seed)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // Where every stream is, a saved game draws the same numbers after loading
+    pub fn snapshot(&self) -> GameRngSnapshot {
+        let mut streams: Vec<(String, String)> = self
+            .streams
+            .iter()
+            .map(|(name, rng)| (name.clone(), rng.get_word_pos().to_string()))
+            .collect();
+        streams.sort();
+        GameRngSnapshot {
+            seed: self.seed,
+            streams,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &GameRngSnapshot) {
+        self.seed = snapshot.seed;
+        self.streams.clear();
+        for (name, word_pos) in snapshot.streams.iter() {
+            if let Ok(word_pos) = word_pos.parse::<u128>() {
+                self.stream(name).set_word_pos(word_pos);
+            }
+        }
+    }
+
+    pub fn stream(&mut self, name: &str) -> &mut ChaCha8Rng {
+        let seed = self.seed;
+        self.streams.entry(name.to_string()).or_insert_with(|| {
+            let mut rng = ChaCha8Rng::seed_from_u64(seed);
+            rng.set_stream(stream_id(name));
+            rng
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameRngSnapshot {
+    pub seed: u64,
+    // (name, word position) per stream, the position is a u128 which not every format can hold,
+    // so it goes in as a string
+    pub streams: Vec<(String, String)>,
+}
+
+// FNV-1a, so the stream of a name never changes between builds (unlike the std hasher)
+fn stream_id(name: &str) -> u64 {
+    let mut hash = 0xcbf29ce484222325_u64;
+    for byte in name.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
@@ -0,0 +1,809 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::Duration;
+
+use bevy::input::*;
+use bevy::prelude::*;
+use bevy::window::*;
+
+use crate::test_bevy::*;
+
+// Save games in saves/*.ron, only while playing:
+// - F5 quicksaves, F9 quickloads
+// - F6 picks the next of the numbered slots, Shift+F5 / Shift+F9 save to / load from it
+// Both happen between two ticks (in PostUpdate), so a loaded game goes on from exactly the tick
+// it was saved at.
+// Loading while recording makes the replay go its own way, the file isn't part of it.
+pub struct SaveGamePlugin;
+
+impl Plugin for SaveGamePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(SaveSlots::default())
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                savegame_keys.system().label("savegame_keys_system"),
+            )
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                savegame_save
+                    .system()
+                    .label("savegame_save_system")
+                    .after("savegame_keys_system"),
+            )
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                savegame_load
+                    .system()
+                    .label("savegame_load_system")
+                    .after("savegame_save_system"),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .with_system(savegame_restore_tiles.system().before("map_spawn_system")),
+            );
+    }
+}
+
+pub const SAVE_VERSION: u32 = 3;
+pub const SAVE_SLOTS: usize = 4;
+const SAVE_DIR: &str = "saves";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveSlot {
+    Quick,
+    // 1..=SAVE_SLOTS
+    Numbered(usize),
+}
+
+impl SaveSlot {
+    pub fn path(&self) -> String {
+        match self {
+            SaveSlot::Quick => format!("{}/quick.ron", SAVE_DIR),
+            SaveSlot::Numbered(slot) => format!("{}/slot{}.ron", SAVE_DIR, slot),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SaveRequest {
+    Save(SaveSlot),
+    Load(SaveSlot),
+}
+
+pub struct SaveSlots {
+    pub selected: usize,
+    // picked up by savegame_save / savegame_load in the same tick
+    pub request: Option<SaveRequest>,
+}
+
+impl Default for SaveSlots {
+    fn default() -> Self {
+        Self {
+            selected: 1,
+            request: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MovementSnapshot {
+    pub position: Vec2,
+    pub speed: Vec2,
+    pub direction: Vec2,
+    pub delta: Vec2,
+}
+
+impl From<&MovementCmp> for MovementSnapshot {
+    fn from(mov: &MovementCmp) -> Self {
+        Self {
+            position: mov.position,
+            speed: mov.speed,
+            direction: mov.direction,
+            delta: mov.delta,
+        }
+    }
+}
+
+impl From<&MovementSnapshot> for MovementCmp {
+    fn from(snapshot: &MovementSnapshot) -> Self {
+        Self {
+            position: snapshot.position,
+            speed: snapshot.speed,
+            direction: snapshot.direction,
+            delta: snapshot.delta,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HitboxSnapshot {
+    pub upper_left: Vec2,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl From<&RectangleHitboxCmp> for HitboxSnapshot {
+    fn from(hitbox: &RectangleHitboxCmp) -> Self {
+        Self {
+            upper_left: hitbox.rect.upper_left.0,
+            width: hitbox.rect.width,
+            height: hitbox.rect.height,
+        }
+    }
+}
+
+impl From<&HitboxSnapshot> for RectangleHitboxCmp {
+    fn from(snapshot: &HitboxSnapshot) -> Self {
+        Self {
+            rect: collision::Rect::new((
+                Point::new(snapshot.upper_left),
+                snapshot.width,
+                snapshot.height,
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthSnapshot {
+    pub current: f32,
+    pub max: f32,
+    pub iframes: f32,
+}
+
+impl From<&HealthCmp> for HealthSnapshot {
+    fn from(health: &HealthCmp) -> Self {
+        Self {
+            current: health.current,
+            max: health.max,
+            iframes: health.iframes,
+        }
+    }
+}
+
+impl From<&HealthSnapshot> for HealthCmp {
+    fn from(snapshot: &HealthSnapshot) -> Self {
+        Self {
+            current: snapshot.current,
+            max: snapshot.max,
+            iframes: snapshot.iframes,
+        }
+    }
+}
+
+// The timers of the game all run once
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimerSnapshot {
+    pub duration: f32,
+    pub elapsed: f32,
+}
+
+impl From<&Timer> for TimerSnapshot {
+    fn from(timer: &Timer) -> Self {
+        Self {
+            duration: timer.duration().as_secs_f32(),
+            elapsed: timer.elapsed_secs(),
+        }
+    }
+}
+
+impl From<&TimerSnapshot> for Timer {
+    fn from(snapshot: &TimerSnapshot) -> Self {
+        let mut timer = Timer::from_seconds(snapshot.duration, false);
+        timer.set_elapsed(Duration::from_secs_f32(snapshot.elapsed));
+        timer
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayerSnapshot {
+    pub id: usize,
+    pub movement: MovementSnapshot,
+    pub hitbox: HitboxSnapshot,
+    pub health: HealthSnapshot,
+    pub velocity: Vec2,
+    pub aim: Vec2,
+    pub dash: DashSnapshot,
+    pub weapon_cooldown: f32,
+    pub invulnerable: Option<TimerSnapshot>,
+    // what is left of the click to move path
+    pub path: Vec<Vec2>,
+}
+
+// What a monster has in mind
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MonsterBrainSnapshot {
+    StateMachine { state: MonsterAiState, home: Vec2 },
+    BehaviorTree { home: Vec2 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SteeringSnapshot {
+    pub seek_target: Option<Vec2>,
+    pub flee_target: Option<Vec2>,
+    pub wander_angle: f32,
+}
+
+impl From<&SteeringCmp> for SteeringSnapshot {
+    fn from(steering: &SteeringCmp) -> Self {
+        Self {
+            seek_target: steering.seek_target,
+            flee_target: steering.flee_target,
+            wander_angle: steering.wander_angle(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MonsterSnapshot {
+    pub archetype: MonsterArchetype,
+    pub movement: MovementSnapshot,
+    pub hitbox: HitboxSnapshot,
+    pub health: HealthSnapshot,
+    // the wave that released it, if any
+    pub wave: Option<usize>,
+    pub brain: Option<MonsterBrainSnapshot>,
+    pub steering: SteeringSnapshot,
+    pub invulnerable: Option<TimerSnapshot>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectileSnapshot {
+    pub hitbox: HitboxSnapshot,
+    pub velocity: Vec2,
+    pub damage: f32,
+    pub lifetime: TimerSnapshot,
+}
+
+// The line of a hitscan shot, fading out
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TracerSnapshot {
+    pub start: Vec2,
+    pub end: Vec2,
+    pub timer: TimerSnapshot,
+}
+
+// A destructible wall that took some hits, by (col, row)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WallSnapshot {
+    pub cell: (usize, usize),
+    pub health: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SaveGame {
+    pub version: u32,
+    pub level: usize,
+    // The current map in the .map format, as it is now: generated or edited maps come back the
+    // same, destroyed walls and collected pickups are taken out of the grid
+    pub map: String,
+    pub triggers: Vec<MapTrigger>,
+    // (col, row) of the open doors
+    pub open_doors: Vec<(usize, usize)>,
+    pub damaged_walls: Vec<WallSnapshot>,
+    pub players: Vec<PlayerSnapshot>,
+    pub monsters: Vec<MonsterSnapshot>,
+    pub projectiles: Vec<ProjectileSnapshot>,
+    pub tracers: Vec<TracerSnapshot>,
+    pub spawner: WaveSpawnerSnapshot,
+    pub score: u32,
+    pub rng: GameRngSnapshot,
+}
+
+impl SaveGame {
+    pub fn write(&self, path: &str) -> Result<(), anyhow::Error> {
+        if let Some(dir) = std::path::Path::new(path).parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::new())?;
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    pub fn read(path: &str) -> Result<Self, anyhow::Error> {
+        let save: SaveGame = ron::de::from_str(&std::fs::read_to_string(path)?)?;
+        if save.version != SAVE_VERSION {
+            anyhow::bail!(
+                "saved as version {}, this build loads version {}",
+                save.version,
+                SAVE_VERSION
+            );
+        }
+        Ok(save)
+    }
+}
+
+// The doors and damaged walls of a loaded game, waiting for the tiles of its map to come out
+pub struct PendingTileState {
+    open_doors: Vec<(usize, usize)>,
+    damaged_walls: Vec<WallSnapshot>,
+}
+
+fn savegame_keys(
+    keyboard_input: Res<Input<KeyCode>>,
+    state: Res<State<AppState>>,
+    mut slots: ResMut<SaveSlots>,
+) {
+    if *state.current() != AppState::Playing {
+        return;
+    }
+    let shift = keyboard_input.pressed(KeyCode::LShift) || keyboard_input.pressed(KeyCode::RShift);
+    if keyboard_input.just_pressed(KeyCode::F6) {
+        slots.selected = slots.selected % SAVE_SLOTS + 1;
+        println!("Save slot {}", slots.selected);
+    }
+    let slot = if shift {
+        SaveSlot::Numbered(slots.selected)
+    } else {
+        SaveSlot::Quick
+    };
+    if keyboard_input.just_pressed(KeyCode::F5) {
+        slots.request = Some(SaveRequest::Save(slot));
+    } else if keyboard_input.just_pressed(KeyCode::F9) {
+        slots.request = Some(SaveRequest::Load(slot));
+    }
+}
+
+// The cell a tile entity sits in, by the center of its hitbox
+fn tile_cell(map: &Map, hitbox: &RectangleHitboxCmp) -> Option<(usize, usize)> {
+    map.cell_at(hitbox.rect.center().0)
+}
+
+fn savegame_save(
+    mut slots: ResMut<SaveSlots>,
+    level: Res<LevelManager>,
+    maps: Res<Assets<Map>>,
+    current_map: Option<Res<CurrentMap>>,
+    game_rng: Res<GameRng>,
+    score: Res<Score>,
+    spawner: Res<WaveSpawner>,
+    player_query: Query<
+        (
+            &PlayerIdCmp,
+            &MovementCmp,
+            &RectangleHitboxCmp,
+            &HealthCmp,
+            &LocomotionCmp,
+            &AimCmp,
+            &DashCmp,
+            &WeaponCmp,
+            Option<&InvulnerableCmp>,
+            &ClickMoveCmp,
+        ),
+        With<PlayerEntity>,
+    >,
+    monster_query: Query<
+        (
+            &MonsterArchetype,
+            &MovementCmp,
+            &RectangleHitboxCmp,
+            &HealthCmp,
+            Option<&WaveMonsterCmp>,
+            (
+                Option<&MonsterAiCmp>,
+                Option<&BehaviorTreeCmp>,
+                &SteeringCmp,
+                Option<&InvulnerableCmp>,
+            ),
+        ),
+        With<MonsterEntity>,
+    >,
+    projectile_query: Query<(&ProjectileCmp, &RectangleHitboxCmp), With<ProjectileEntity>>,
+    tracer_query: Query<(&TracerCmp, &RayCmp)>,
+    wall_query: Query<(&RectangleHitboxCmp, &HealthCmp), (With<WallEntity>, With<MapTileCmp>)>,
+    door_query: Query<(&RectangleHitboxCmp, &DoorCmp)>,
+    pickup_query: Query<&RectangleHitboxCmp, With<PickupCmp>>,
+) {
+    let slot = match slots.request {
+        Some(SaveRequest::Save(slot)) => slot,
+        _ => return,
+    };
+    slots.request = None;
+    let map = match current_map.and_then(|current_map| maps.get(&current_map.0)) {
+        Some(map) => map,
+        None => {
+            println!("Nothing to save, the map isn't loaded yet");
+            return;
+        }
+    };
+
+    // a destructible wall or pickup cell without its entity has been destroyed / collected
+    let standing: HashSet<(usize, usize)> = wall_query
+        .iter()
+        .map(|(wall_hitbox, _)| wall_hitbox)
+        .chain(pickup_query.iter())
+        .filter_map(|hitbox| tile_cell(map, hitbox))
+        .collect();
+    let empty_glyph = map
+        .legend()
+        .0
+        .iter()
+        .find(|(_, kind)| **kind == TileKind::Empty)
+        .map_or(' ', |(glyph, _)| *glyph);
+    let mut grid = map.grid();
+    for (row, kinds) in map.tile_grid().iter().enumerate() {
+        for (col, kind) in kinds.iter().enumerate() {
+            let removable = matches!(
+                kind,
+                Some(TileKind::DestructibleWall) | Some(TileKind::HealthPickup)
+            );
+            if removable && !standing.contains(&(col, row)) {
+                grid[row][col] = empty_glyph;
+            }
+        }
+    }
+
+    let save = SaveGame {
+        version: SAVE_VERSION,
+        level: level.current,
+        map: map_to_string(&map.with_grid(&grid)),
+        triggers: map.triggers().to_vec(),
+        open_doors: door_query
+            .iter()
+            .filter(|(_, door)| door.is_open())
+            .filter_map(|(door_hitbox, _)| tile_cell(map, door_hitbox))
+            .collect(),
+        damaged_walls: wall_query
+            .iter()
+            .filter(|(_, wall_health)| wall_health.current() < wall_health.max())
+            .filter_map(|(wall_hitbox, wall_health)| {
+                tile_cell(map, wall_hitbox).map(|cell| WallSnapshot {
+                    cell,
+                    health: wall_health.current(),
+                })
+            })
+            .collect(),
+        players: player_query
+            .iter()
+            .map(
+                |(
+                    player_id,
+                    player_mov,
+                    player_hitbox,
+                    player_health,
+                    locomotion,
+                    aim,
+                    dash,
+                    weapon,
+                    invulnerable,
+                    click_move,
+                )| PlayerSnapshot {
+                    id: player_id.0,
+                    movement: player_mov.into(),
+                    hitbox: player_hitbox.into(),
+                    health: player_health.into(),
+                    velocity: locomotion.velocity(),
+                    aim: aim.direction,
+                    dash: dash.snapshot(),
+                    weapon_cooldown: weapon.cooldown_left(),
+                    invulnerable: invulnerable.map(|invulnerable| (&invulnerable.timer).into()),
+                    path: click_move.path.clone(),
+                },
+            )
+            .collect(),
+        monsters: monster_query
+            .iter()
+            .map(
+                |(
+                    archetype,
+                    monster_mov,
+                    monster_hitbox,
+                    monster_health,
+                    wave,
+                    (monster_ai, monster_bt, steering, invulnerable),
+                )| MonsterSnapshot {
+                    archetype: *archetype,
+                    movement: monster_mov.into(),
+                    hitbox: monster_hitbox.into(),
+                    health: monster_health.into(),
+                    wave: wave.map(|wave| wave.wave),
+                    brain: match (monster_ai, monster_bt) {
+                        (Some(monster_ai), _) => Some(MonsterBrainSnapshot::StateMachine {
+                            state: monster_ai.state,
+                            home: monster_ai.home,
+                        }),
+                        (None, Some(monster_bt)) => Some(MonsterBrainSnapshot::BehaviorTree {
+                            home: monster_bt.home,
+                        }),
+                        (None, None) => None,
+                    },
+                    steering: steering.into(),
+                    invulnerable: invulnerable.map(|invulnerable| (&invulnerable.timer).into()),
+                },
+            )
+            .collect(),
+        projectiles: projectile_query
+            .iter()
+            .map(|(projectile, projectile_hitbox)| ProjectileSnapshot {
+                hitbox: projectile_hitbox.into(),
+                velocity: projectile.velocity,
+                damage: projectile.damage,
+                lifetime: (&projectile.lifetime).into(),
+            })
+            .collect(),
+        tracers: tracer_query
+            .iter()
+            .map(|(tracer, ray)| TracerSnapshot {
+                start: ray.ray.start.0,
+                end: ray.ray.end.0,
+                timer: (&tracer.timer).into(),
+            })
+            .collect(),
+        spawner: spawner.snapshot(),
+        score: score.points,
+        rng: game_rng.snapshot(),
+    };
+    let path = slot.path();
+    match save.write(&path) {
+        Ok(()) => println!("Saved to {}", path),
+        Err(err) => println!("Can't save to {}: {}", path, err),
+    }
+}
+
+fn savegame_load(
+    mut commands: Commands,
+    mut slots: ResMut<SaveSlots>,
+    materials: Res<Materials>,
+    config: Res<GlobalConfig>,
+    asset_server: Res<AssetServer>,
+    mut maps: ResMut<Assets<Map>>,
+    mut level: ResMut<LevelManager>,
+    mut game_rng: ResMut<GameRng>,
+    mut score: ResMut<Score>,
+    mut spawner: ResMut<WaveSpawner>,
+    entity_query: Query<
+        Entity,
+        Or<(
+            With<PlayerEntity>,
+            With<MonsterEntity>,
+            With<ProjectileEntity>,
+            With<TracerCmp>,
+        )>,
+    >,
+) {
+    let slot = match slots.request {
+        Some(SaveRequest::Load(slot)) => slot,
+        _ => return,
+    };
+    slots.request = None;
+    let path = slot.path();
+    let save = match SaveGame::read(&path) {
+        Ok(save) => save,
+        Err(err) => {
+            println!("Can't load {}: {}", path, err);
+            return;
+        }
+    };
+    let map = match parse_map(&save.map) {
+        Ok(map) => map.with_triggers(save.triggers.clone()),
+        Err(err) => {
+            println!("Can't load {}, the map is broken: {}", path, err);
+            return;
+        }
+    };
+
+    // everything that moves comes from the save, map_spawn replaces the tiles
+    for entity in entity_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.insert_resource(CurrentMap(maps.add(map)));
+    commands.insert_resource(PendingTileState {
+        open_doors: save.open_doors.clone(),
+        damaged_walls: save.damaged_walls.clone(),
+    });
+    level.set_current(save.level);
+    game_rng.restore(&save.rng);
+    score.points = save.score;
+    spawner.restore(&save.spawner);
+
+    for player in save.players.iter() {
+        let mut locomotion = LocomotionCmp::player(&config);
+        locomotion.set_velocity(player.velocity);
+        let mut dash = DashCmp::player(&config);
+        dash.restore(&player.dash);
+        let mut weapon = WeaponCmp::from_args();
+        weapon.set_cooldown_left(player.weapon_cooldown);
+        let entity = spawn_player(
+            &mut commands,
+            &materials,
+            &config,
+            player.id,
+            player.movement.position,
+        );
+        commands
+            .entity(entity)
+            .insert(MovementCmp::from(&player.movement))
+            .insert(RectangleHitboxCmp::from(&player.hitbox))
+            .insert(HealthCmp::from(&player.health))
+            .insert(locomotion)
+            .insert(AimCmp {
+                direction: player.aim,
+            })
+            .insert(dash)
+            .insert(weapon)
+            .insert(ClickMoveCmp {
+                path: player.path.clone(),
+            });
+        if let Some(invulnerable) = &player.invulnerable {
+            commands.entity(entity).insert(InvulnerableCmp {
+                timer: invulnerable.into(),
+            });
+        }
+    }
+    for monster in save.monsters.iter() {
+        let entity = spawn_monster(
+            &mut commands,
+            &materials,
+            &config,
+            Point::new(monster.movement.position),
+            monster.archetype,
+        );
+        commands
+            .entity(entity)
+            .insert(MovementCmp::from(&monster.movement))
+            .insert(RectangleHitboxCmp::from(&monster.hitbox))
+            .insert(HealthCmp::from(&monster.health));
+        if let Some(wave) = monster.wave {
+            commands.entity(entity).insert(WaveMonsterCmp { wave });
+        }
+        match &monster.brain {
+            Some(MonsterBrainSnapshot::StateMachine { state, home }) => {
+                let mut monster_ai = MonsterAiCmp::new(monster.archetype.ai_config(), *home);
+                monster_ai.state = *state;
+                commands.entity(entity).insert(monster_ai);
+            }
+            Some(MonsterBrainSnapshot::BehaviorTree { home }) => {
+                if let Some(path) = monster.archetype.behavior_tree() {
+                    commands
+                        .entity(entity)
+                        .remove::<MonsterAiCmp>()
+                        .insert(BehaviorTreeCmp {
+                            tree: asset_server.load(path),
+                            home: *home,
+                        });
+                }
+            }
+            None => {}
+        }
+        let mut steering = SteeringCmp::flock();
+        steering.seek_target = monster.steering.seek_target;
+        steering.flee_target = monster.steering.flee_target;
+        steering.set_wander_angle(monster.steering.wander_angle);
+        commands.entity(entity).insert(steering);
+        if let Some(invulnerable) = &monster.invulnerable {
+            commands.entity(entity).insert(InvulnerableCmp {
+                timer: invulnerable.into(),
+            });
+        }
+    }
+    for projectile in save.projectiles.iter() {
+        let hitbox = RectangleHitboxCmp::from(&projectile.hitbox);
+        spawn_projectile_entity(
+            &mut commands,
+            &materials,
+            hitbox.rect.center(),
+            projectile.hitbox.width,
+            ProjectileCmp {
+                velocity: projectile.velocity,
+                damage: projectile.damage,
+                lifetime: (&projectile.lifetime).into(),
+            },
+        );
+    }
+    for tracer in save.tracers.iter() {
+        let ray = collision::Ray {
+            start: Point::new(tracer.start),
+            end: Point::new(tracer.end),
+        };
+        let entity = spawn_tracer(&mut commands, &materials, ray);
+        commands.entity(entity).insert(TracerCmp {
+            timer: (&tracer.timer).into(),
+        });
+    }
+    println!("Loaded {}", path);
+}
+
+// Runs a tick after map_spawn sent its event, by then the tiles it spawned are queryable
+fn savegame_restore_tiles(
+    mut commands: Commands,
+    pending: Option<Res<PendingTileState>>,
+    maps: Res<Assets<Map>>,
+    current_map: Res<CurrentMap>,
+    mut map_spawned_events: EventReader<MapSpawnedEvent>,
+    mut door_query: Query<(
+        &mut DoorCmp,
+        &mut CollideCmp,
+        &mut Visible,
+        &RectangleHitboxCmp,
+    )>,
+    mut wall_query: Query<
+        (&mut HealthCmp, &RectangleHitboxCmp),
+        (With<WallEntity>, With<MapTileCmp>),
+    >,
+) {
+    let spawned = map_spawned_events.iter().count() > 0;
+    let (pending, map) = match (pending, maps.get(&current_map.0)) {
+        (Some(pending), Some(map)) if spawned => (pending, map),
+        _ => return,
+    };
+    for (mut door, mut door_collide, mut door_visible, door_hitbox) in door_query.iter_mut() {
+        let open =
+            tile_cell(map, door_hitbox).is_some_and(|cell| pending.open_doors.contains(&cell));
+        set_door_open(&mut door, &mut door_collide, &mut door_visible, open);
+    }
+    for (mut wall_health, wall_hitbox) in wall_query.iter_mut() {
+        let cell = tile_cell(map, wall_hitbox);
+        if let Some(wall) = pending
+            .damaged_walls
+            .iter()
+            .find(|wall| Some(wall.cell) == cell)
+        {
+            wall_health.current = wall.health;
+        }
+    }
+    commands.remove_resource::<PendingTileState>();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::RngCore;
+
+    fn sample_save() -> SaveGame {
+        let mut rng = GameRng::new(42);
+        rng.stream("monster_spawner").next_u32();
+        SaveGame {
+            version: SAVE_VERSION,
+            level: 2,
+            map: STR("name: Saved\n---\n***\n*P*\n***"),
+            triggers: vec![MapTrigger {
+                name: STR("exit"),
+                offset: Vec2::new(20., 80.),
+                size: Vec2::new(20., 20.),
+            }],
+            open_doors: vec![(3, 4)],
+            damaged_walls: vec![WallSnapshot {
+                cell: (1, 2),
+                health: 5.,
+            }],
+            players: Vec::new(),
+            monsters: Vec::new(),
+            projectiles: Vec::new(),
+            tracers: Vec::new(),
+            spawner: WaveSpawner::survival().snapshot(),
+            score: 120,
+            rng: rng.snapshot(),
+        }
+    }
+
+    // in the temp dir, one file per test so they can run in parallel
+    fn save_path(name: &str) -> String {
+        let file = format!("savegame_{}_{}.ron", name, std::process::id());
+        let path = std::env::temp_dir().join(file);
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = save_path("round_trip");
+        let save = sample_save();
+        save.write(&path).unwrap();
+        let loaded = SaveGame::read(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            ron::ser::to_string(&loaded.unwrap()).unwrap(),
+            ron::ser::to_string(&save).unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let path = save_path("other_version");
+        let mut save = sample_save();
+        save.version = SAVE_VERSION + 1;
+        save.write(&path).unwrap();
+        let loaded = SaveGame::read(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(loaded.is_err());
+    }
+}
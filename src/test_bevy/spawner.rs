@@ -0,0 +1,274 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use bevy::input::*;
+use bevy::prelude::*;
+use bevy::window::*;
+
+use crate::test_bevy::*;
+
+pub struct SpawnerPlugin;
+
+impl Plugin for SpawnerPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(WaveSpawner::survival())
+            .add_event::<WaveStartedEvent>()
+            .add_event::<WaveClearedEvent>()
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .with_system(wave_spawner.system().label("wave_spawner_system"))
+                    .with_system(wave_announcer.system().after("wave_spawner_system")),
+            )
+            .add_system_set(
+                SystemSet::on_exit(AppState::Playing).with_system(spawner_teardown.system()),
+            );
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Wave {
+    pub archetype: MonsterArchetype,
+    pub count: u32,
+    // seconds to wait before the wave starts (counted from the previous wave being cleared)
+    pub delay: f32,
+    // seconds between two monsters of the same wave
+    pub spawn_interval: f32,
+}
+
+#[derive(Debug)]
+pub enum WaveSpawnerState {
+    // counting down the delay of waves[current]
+    Waiting(Timer),
+    // releasing the monsters of waves[current]
+    Spawning { spawned: u32, timer: Timer },
+    // everything is out, waiting for the player to kill them all
+    Fighting,
+    // no waves left
+    Done,
+}
+
+pub struct WaveSpawner {
+    pub waves: Vec<Wave>,
+    // the spawner holds back while this many wave monsters are alive
+    pub max_alive: usize,
+    pub current: usize,
+    pub state: WaveSpawnerState,
+    next_spawn_point: usize,
+}
+
+impl WaveSpawner {
+    pub fn new(waves: Vec<Wave>, max_alive: usize) -> Self {
+        let state = match waves.first() {
+            Some(wave) => WaveSpawnerState::Waiting(Timer::from_seconds(wave.delay, false)),
+            None => WaveSpawnerState::Done,
+        };
+        Self {
+            waves,
+            max_alive,
+            current: 0,
+            state,
+            next_spawn_point: 0,
+        }
+    }
+
+    pub fn survival() -> Self {
+        Self::new(
+            vec![
+                Wave {
+                    archetype: MonsterArchetype::Grunt,
+                    count: 4,
+                    delay: 2.,
+                    spawn_interval: 0.5,
+                },
+                Wave {
+                    archetype: MonsterArchetype::Coward,
+                    count: 6,
+                    delay: 5.,
+                    spawn_interval: 0.4,
+                },
+                Wave {
+                    archetype: MonsterArchetype::Grunt,
+                    count: 8,
+                    delay: 5.,
+                    spawn_interval: 0.3,
+                },
+                Wave {
+                    archetype: MonsterArchetype::Stalker,
+                    count: 4,
+                    delay: 5.,
+                    spawn_interval: 0.5,
+                },
+                Wave {
+                    archetype: MonsterArchetype::Skirmisher,
+                    count: 6,
+                    delay: 5.,
+                    spawn_interval: 0.3,
+                },
+                Wave {
+                    archetype: MonsterArchetype::Berserker,
+                    count: 3,
+                    delay: 8.,
+                    spawn_interval: 1.,
+                },
+            ],
+            6,
+        )
+    }
+
+    // How far the waves got, the waves themselves come from the code
+    pub fn snapshot(&self) -> WaveSpawnerSnapshot {
+        let state = match &self.state {
+            WaveSpawnerState::Waiting(timer) => WaveStateSnapshot::Waiting {
+                elapsed: timer.elapsed_secs(),
+            },
+            WaveSpawnerState::Spawning { spawned, timer } => WaveStateSnapshot::Spawning {
+                spawned: *spawned,
+                elapsed: timer.elapsed_secs(),
+            },
+            WaveSpawnerState::Fighting => WaveStateSnapshot::Fighting,
+            WaveSpawnerState::Done => WaveStateSnapshot::Done,
+        };
+        WaveSpawnerSnapshot {
+            current: self.current,
+            next_spawn_point: self.next_spawn_point,
+            state,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &WaveSpawnerSnapshot) {
+        let timer = |seconds: f32, repeating: bool, elapsed: f32| {
+            let mut timer = Timer::from_seconds(seconds, repeating);
+            timer.set_elapsed(Duration::from_secs_f32(elapsed));
+            timer
+        };
+        self.current = snapshot.current;
+        self.next_spawn_point = snapshot.next_spawn_point;
+        self.state = match (self.waves.get(self.current), &snapshot.state) {
+            (Some(wave), WaveStateSnapshot::Waiting { elapsed }) => {
+                WaveSpawnerState::Waiting(timer(wave.delay, false, *elapsed))
+            }
+            (Some(wave), WaveStateSnapshot::Spawning { spawned, elapsed }) => {
+                WaveSpawnerState::Spawning {
+                    spawned: *spawned,
+                    timer: timer(wave.spawn_interval, true, *elapsed),
+                }
+            }
+            (Some(_), WaveStateSnapshot::Fighting) => WaveSpawnerState::Fighting,
+            _ => WaveSpawnerState::Done,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum WaveStateSnapshot {
+    Waiting { elapsed: f32 },
+    Spawning { spawned: u32, elapsed: f32 },
+    Fighting,
+    Done,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WaveSpawnerSnapshot {
+    pub current: usize,
+    pub next_spawn_point: usize,
+    pub state: WaveStateSnapshot,
+}
+
+// Tags a monster with the wave that released it
+pub struct WaveMonsterCmp {
+    pub wave: usize,
+}
+
+pub struct WaveStartedEvent {
+    pub wave: usize,
+}
+
+pub struct WaveClearedEvent {
+    pub wave: usize,
+}
+
+fn wave_spawner(
+    mut commands: Commands,
+    materials: Res<Materials>,
+    config: Res<GlobalConfig>,
+    spawn_points: Res<MonsterSpawnPoints>,
+    mut spawner: ResMut<WaveSpawner>,
+    alive_query: Query<&WaveMonsterCmp, With<MonsterEntity>>,
+    mut wave_started_events: EventWriter<WaveStartedEvent>,
+    mut wave_cleared_events: EventWriter<WaveClearedEvent>,
+) {
+    if spawn_points.0.is_empty() {
+        return;
+    }
+    let alive = alive_query.iter().count();
+    let spawner = &mut *spawner;
+    let current = spawner.current;
+
+    match &mut spawner.state {
+        WaveSpawnerState::Waiting(timer) => {
+            if timer.tick(tick_duration()).finished() {
+                let wave = &spawner.waves[current];
+                spawner.state = WaveSpawnerState::Spawning {
+                    spawned: 0,
+                    // the first monster comes out right away
+                    timer: Timer::from_seconds(wave.spawn_interval, true),
+                };
+                wave_started_events.send(WaveStartedEvent { wave: current });
+            }
+        }
+        WaveSpawnerState::Spawning { spawned, timer } => {
+            let wave = &spawner.waves[current];
+            let ready = *spawned == 0 || timer.tick(tick_duration()).just_finished();
+            if ready && *spawned < wave.count && alive < spawner.max_alive {
+                let sp = spawn_points.0[spawner.next_spawn_point % spawn_points.0.len()];
+                spawner.next_spawn_point += 1;
+                let monster = spawn_monster(
+                    &mut commands,
+                    &materials,
+                    &config,
+                    Point::new(sp),
+                    wave.archetype,
+                );
+                commands
+                    .entity(monster)
+                    .insert(WaveMonsterCmp { wave: current });
+                *spawned += 1;
+            }
+            if *spawned >= wave.count {
+                spawner.state = WaveSpawnerState::Fighting;
+            }
+        }
+        WaveSpawnerState::Fighting => {
+            if alive_query.iter().all(|m| m.wave != current) {
+                wave_cleared_events.send(WaveClearedEvent { wave: current });
+                spawner.current += 1;
+                spawner.state = match spawner.waves.get(spawner.current) {
+                    Some(wave) => WaveSpawnerState::Waiting(Timer::from_seconds(wave.delay, false)),
+                    None => WaveSpawnerState::Done,
+                };
+            }
+        }
+        WaveSpawnerState::Done => {}
+    }
+}
+
+// the next game starts over from the first wave
+fn spawner_teardown(mut spawner: ResMut<WaveSpawner>) {
+    *spawner = WaveSpawner::survival();
+}
+
+fn wave_announcer(
+    spawner: Res<WaveSpawner>,
+    mut wave_started_events: EventReader<WaveStartedEvent>,
+    mut wave_cleared_events: EventReader<WaveClearedEvent>,
+) {
+    for event in wave_started_events.iter() {
+        println!("Wave {} started", event.wave + 1);
+    }
+    for event in wave_cleared_events.iter() {
+        println!("Wave {} cleared", event.wave + 1);
+        if event.wave + 1 == spawner.waves.len() {
+            println!("All waves cleared, you survived!");
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use bevy::input::*;
+use bevy::prelude::*;
+use bevy::window::*;
+
+use crate::test_bevy::*;
+
+// The game starts in the main menu. Confirm (Enter) starts a game, Pause (Escape) pauses it on
+// top of the running game, and the last player's death ends it. Leaving Playing tears the game
+// down (every plugin cleans up after itself on exit), so the next game starts from scratch
+// without relaunching. The screens go through the actions, so they end up in a replay too.
+pub struct GameStatePlugin;
+
+impl Plugin for GameStatePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_state(AppState::MainMenu)
+            .add_system(
+                state_input
+                    .system()
+                    .label("state_input_system")
+                    .after("input_actions_system"),
+            )
+            .add_system_set(
+                SystemSet::on_enter(AppState::MainMenu).with_system(main_menu_enter.system()),
+            )
+            .add_system_set(
+                SystemSet::on_exit(AppState::MainMenu).with_system(screen_exit.system()),
+            )
+            .add_system_set(
+                SystemSet::on_enter(AppState::Paused).with_system(paused_enter.system()),
+            )
+            .add_system_set(SystemSet::on_exit(AppState::Paused).with_system(screen_exit.system()))
+            .add_system_set(
+                SystemSet::on_enter(AppState::GameOver).with_system(game_over_enter.system()),
+            )
+            .add_system_set(
+                SystemSet::on_exit(AppState::GameOver).with_system(screen_exit.system()),
+            );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AppState {
+    MainMenu,
+    Playing,
+    // pushed on top of Playing, the game stays as it is underneath
+    Paused,
+    GameOver,
+}
+
+const SCREEN_FONT_PATH: &str = "C:\\Windows\\Fonts\\arial.ttf";
+
+// The text of the menu, pause and game over screens
+pub struct ScreenEntity;
+
+fn spawn_screen(
+    commands: &mut Commands,
+    asset_server: &Res<AssetServer>,
+    materials: &Res<Materials>,
+    title: &str,
+    hint: &str,
+) {
+    let font = asset_server.load(SCREEN_FONT_PATH);
+    let text = |value: &str, font_size: f32| TextBundle {
+        text: Text::with_section(
+            value,
+            TextStyle {
+                font: font.clone(),
+                font_size,
+                color: Color::WHITE,
+            },
+            TextAlignment {
+                horizontal: HorizontalAlign::Center,
+                ..Default::default()
+            },
+        ),
+        ..Default::default()
+    };
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                position_type: PositionType::Absolute,
+                // the ui goes bottom to top, the title ends up above the hint
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: materials.screen_materials.clone(),
+            ..Default::default()
+        })
+        .insert(ScreenEntity)
+        .with_children(|parent| {
+            parent.spawn_bundle(text(title, 60.));
+            parent.spawn_bundle(text(hint, 24.));
+        });
+}
+
+fn main_menu_enter(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    materials: Res<Materials>,
+) {
+    spawn_screen(
+        &mut commands,
+        &asset_server,
+        &materials,
+        "Test Bevy",
+        "Enter to play",
+    );
+}
+
+fn paused_enter(mut commands: Commands, asset_server: Res<AssetServer>, materials: Res<Materials>) {
+    spawn_screen(
+        &mut commands,
+        &asset_server,
+        &materials,
+        "Paused",
+        "Escape to go on, Enter to quit to the menu",
+    );
+}
+
+fn game_over_enter(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    materials: Res<Materials>,
+    score: Res<Score>,
+) {
+    spawn_screen(
+        &mut commands,
+        &asset_server,
+        &materials,
+        "Game over",
+        &format!(
+            "Score {} - Enter to play again, Escape for the menu",
+            score.points
+        ),
+    );
+}
+
+fn screen_exit(mut commands: Commands, screen_query: Query<Entity, With<ScreenEntity>>) {
+    for screen in screen_query.iter() {
+        commands.entity(screen).despawn_recursive();
+    }
+}
+
+fn state_input(action_state: Res<ActionState>, mut state: ResMut<State<AppState>>) {
+    // any player can drive the screens
+    let just_pressed = |action: InputAction| {
+        action_state
+            .players()
+            .iter()
+            .any(|actions| actions.just_pressed(action))
+    };
+    let current = *state.current();
+    let result = match current {
+        AppState::MainMenu if just_pressed(InputAction::Confirm) => state.set(AppState::Playing),
+        AppState::Playing if just_pressed(InputAction::Pause) => state.push(AppState::Paused),
+        AppState::Paused if just_pressed(InputAction::Pause) => state.pop(),
+        // leaves the paused game too, it gets torn down on the way
+        AppState::Paused if just_pressed(InputAction::Confirm) => state.replace(AppState::MainMenu),
+        AppState::GameOver if just_pressed(InputAction::Confirm) => state.set(AppState::Playing),
+        AppState::GameOver if just_pressed(InputAction::Pause) => state.set(AppState::MainMenu),
+        _ => Ok(()),
+    };
+    // e.g. the game ended in the same tick
+    if let Err(err) = result {
+        println!("Can't change the game state: {:?}", err);
+    }
+}
@@ -0,0 +1,358 @@
+use rand::Rng;
+
+use bevy::input::*;
+use bevy::prelude::*;
+use bevy::window::*;
+
+use crate::test_bevy::*;
+
+pub struct SteeringPlugin;
+
+impl Plugin for SteeringPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_set(
+            SystemSet::on_update(AppState::Playing).with_system(
+                monster_steering
+                    .system()
+                    .label("monster_steering_system")
+                    .after("monster_ai_system")
+                    .before("monster_collision_system"),
+            ),
+        );
+    }
+}
+
+// Reynolds-style steering behaviors. Every behavior outputs a desired velocity (px/s) and the
+// weighted sum of all of them is what the entity tries to reach.
+#[derive(Debug, Clone)]
+pub enum SteeringBehavior {
+    // go straight for seek_target
+    Seek,
+    // run straight away from flee_target
+    Flee,
+    // go for seek_target but slow down once inside slowing_radius
+    Arrive {
+        slowing_radius: f32,
+    },
+    // smooth random walk: a point on a circle in front of the entity drifts by up to jitter rad/s
+    Wander {
+        radius: f32,
+        distance: f32,
+        jitter: f32,
+    },
+    // look look_ahead px along the current velocity and push away from the first wall in the way
+    AvoidWalls {
+        look_ahead: f32,
+    },
+    // keep away from neighbors closer than radius
+    Separation {
+        radius: f32,
+    },
+    // match the heading of neighbors within radius
+    Alignment {
+        radius: f32,
+    },
+    // move toward the center of the neighbors within radius
+    Cohesion {
+        radius: f32,
+    },
+}
+
+pub struct SteeringCmp {
+    pub behaviors: Vec<(SteeringBehavior, f32)>,
+    // how fast (px/s^2) the velocity can turn toward the desired one
+    pub max_force: f32,
+    pub seek_target: Option<Vec2>,
+    pub flee_target: Option<Vec2>,
+    wander_angle: f32,
+}
+
+impl SteeringCmp {
+    pub fn new(behaviors: Vec<(SteeringBehavior, f32)>, max_force: f32) -> Self {
+        Self {
+            behaviors,
+            max_force,
+            seek_target: None,
+            flee_target: None,
+            wander_angle: 0.,
+        }
+    }
+
+    pub fn wander_angle(&self) -> f32 {
+        self.wander_angle
+    }
+
+    pub fn set_wander_angle(&mut self, wander_angle: f32) {
+        self.wander_angle = wander_angle;
+    }
+
+    pub fn flock() -> Self {
+        Self::new(
+            vec![
+                (
+                    SteeringBehavior::Arrive {
+                        slowing_radius: 60.,
+                    },
+                    1.,
+                ),
+                (SteeringBehavior::Flee, 1.),
+                (
+                    SteeringBehavior::Wander {
+                        radius: 30.,
+                        distance: 60.,
+                        jitter: 4.,
+                    },
+                    0.4,
+                ),
+                (SteeringBehavior::AvoidWalls { look_ahead: 60. }, 2.),
+                (SteeringBehavior::Separation { radius: 50. }, 1.5),
+                (SteeringBehavior::Alignment { radius: 100. }, 0.5),
+                (SteeringBehavior::Cohesion { radius: 100. }, 0.5),
+            ],
+            3000.,
+        )
+    }
+}
+
+// What a steered entity knows about one of its neighbors
+#[derive(Debug, Clone, Copy)]
+pub struct SteeringNeighbor {
+    pub entity: Entity,
+    pub position: Vec2,
+    pub velocity: Vec2,
+}
+
+pub fn steer_seek(position: Vec2, target: Vec2, max_speed: f32) -> Vec2 {
+    (target - position).normalize_or_zero() * max_speed
+}
+
+pub fn steer_flee(position: Vec2, target: Vec2, max_speed: f32) -> Vec2 {
+    -steer_seek(position, target, max_speed)
+}
+
+pub fn steer_arrive(position: Vec2, target: Vec2, max_speed: f32, slowing_radius: f32) -> Vec2 {
+    let to_target = target - position;
+    let distance = to_target.length();
+    if distance < f32::EPSILON {
+        return Vec2::ZERO;
+    }
+    let speed = if distance < slowing_radius {
+        max_speed * distance / slowing_radius
+    } else {
+        max_speed
+    };
+    to_target / distance * speed
+}
+
+pub fn steer_wander(
+    velocity: Vec2,
+    wander_angle: &mut f32,
+    radius: f32,
+    distance: f32,
+    jitter: f32,
+    max_speed: f32,
+    rng: &mut impl Rng,
+) -> Vec2 {
+    *wander_angle += rng.gen_range(-1_f32..1_f32) * jitter * TIME_STEP;
+    let heading = match velocity.try_normalize() {
+        Some(heading) => heading,
+        None => Vec2::new(wander_angle.cos(), wander_angle.sin()),
+    };
+    let circle_center = heading * distance;
+    let displacement = Vec2::new(wander_angle.cos(), wander_angle.sin()) * radius;
+    (circle_center + displacement).normalize_or_zero() * max_speed
+}
+
+// position is the center of the entity, size its hitbox size
+pub fn steer_avoid_walls<'a, I>(
+    position: Vec2,
+    size: Vec2,
+    velocity: Vec2,
+    look_ahead: f32,
+    max_speed: f32,
+    walls: I,
+) -> Vec2
+where
+    I: IntoIterator<Item = &'a collision::Rect>,
+{
+    let heading = match velocity.try_normalize() {
+        Some(heading) => heading,
+        None => return Vec2::ZERO,
+    };
+    let ray = collision::Ray {
+        start: Point::new(position),
+        end: Point::new(position + heading * look_ahead),
+    };
+
+    let mut closest: Option<(f32, RectCollisionSide)> = None;
+    for wall in walls {
+        // same trick as is_rect_collide: grow the wall by the entity so a point ray is enough
+        let expanded_wall = collision::Rect::new((
+            wall.upper_left - Point::new((size.x / 2., size.y / 2.)),
+            wall.width + size.x,
+            wall.height + size.y,
+        ));
+        let (collided, _, contact_normal, contact_time) = expanded_wall.is_ray_intersect(&ray);
+        if collided
+            && contact_time.unwrap() >= 0.
+            && contact_time.unwrap() <= 1.
+            && closest.is_none_or(|(t, _)| contact_time.unwrap() < t)
+        {
+            closest = Some((contact_time.unwrap(), contact_normal.unwrap()));
+        }
+    }
+
+    match closest {
+        // the sooner the hit, the harder the push
+        Some((t, side)) => rect_collision_side_to_vec2(side) * max_speed * (1. - t),
+        None => Vec2::ZERO,
+    }
+}
+
+pub fn steer_separation(
+    entity: Entity,
+    position: Vec2,
+    neighbors: &[SteeringNeighbor],
+    radius: f32,
+    max_speed: f32,
+) -> Vec2 {
+    let mut push = Vec2::ZERO;
+    for neighbor in neighbors.iter().filter(|n| n.entity != entity) {
+        let away = position - neighbor.position;
+        let distance = away.length();
+        if distance > f32::EPSILON && distance < radius {
+            // closer neighbors push harder
+            push += away / distance * (1. - distance / radius);
+        }
+    }
+    push.normalize_or_zero() * max_speed
+}
+
+pub fn steer_alignment(
+    entity: Entity,
+    position: Vec2,
+    neighbors: &[SteeringNeighbor],
+    radius: f32,
+    max_speed: f32,
+) -> Vec2 {
+    let mut heading = Vec2::ZERO;
+    for neighbor in neighbors.iter().filter(|n| n.entity != entity) {
+        if position.distance(neighbor.position) < radius {
+            heading += neighbor.velocity;
+        }
+    }
+    heading.normalize_or_zero() * max_speed
+}
+
+pub fn steer_cohesion(
+    entity: Entity,
+    position: Vec2,
+    neighbors: &[SteeringNeighbor],
+    radius: f32,
+    max_speed: f32,
+) -> Vec2 {
+    let mut center = Vec2::ZERO;
+    let mut count = 0;
+    for neighbor in neighbors.iter().filter(|n| n.entity != entity) {
+        if position.distance(neighbor.position) < radius {
+            center += neighbor.position;
+            count += 1;
+        }
+    }
+    if count == 0 {
+        return Vec2::ZERO;
+    }
+    steer_seek(position, center / count as f32, max_speed)
+}
+
+fn monster_steering(
+    mut game_rng: ResMut<GameRng>,
+    mut monster_query: Query<
+        (
+            Entity,
+            &mut SteeringCmp,
+            &mut MovementCmp,
+            &RectangleHitboxCmp,
+        ),
+        With<MonsterEntity>,
+    >,
+    wall_query: Query<&RectangleHitboxCmp, With<WallEntity>>,
+) {
+    let rng = game_rng.stream("steering_wander");
+
+    // snapshot everyone first so the flock rules see the same state regardless of the order
+    let neighbors: Vec<SteeringNeighbor> = monster_query
+        .iter_mut()
+        .map(
+            |(entity, _, monster_mov, monster_hitbox)| SteeringNeighbor {
+                entity,
+                position: monster_hitbox.rect.center().get_vec2(),
+                velocity: monster_mov.direction * monster_mov.speed,
+            },
+        )
+        .collect();
+
+    for (entity, mut steering, mut monster_mov, monster_hitbox) in monster_query.iter_mut() {
+        let position = monster_hitbox.rect.center().get_vec2();
+        let size = Vec2::new(monster_hitbox.rect.width, monster_hitbox.rect.height);
+        // the current direction may have been changed by a collision, so that's the real velocity
+        let velocity = monster_mov.direction * monster_mov.speed;
+        let max_speed = monster_mov.speed.max_element();
+
+        let mut desired = Vec2::ZERO;
+        let mut wander_angle = steering.wander_angle;
+        for (behavior, weight) in steering.behaviors.iter() {
+            let force = match *behavior {
+                SteeringBehavior::Seek => steering
+                    .seek_target
+                    .map_or(Vec2::ZERO, |t| steer_seek(position, t, max_speed)),
+                SteeringBehavior::Flee => steering
+                    .flee_target
+                    .map_or(Vec2::ZERO, |t| steer_flee(position, t, max_speed)),
+                SteeringBehavior::Arrive { slowing_radius } => {
+                    steering.seek_target.map_or(Vec2::ZERO, |t| {
+                        steer_arrive(position, t, max_speed, slowing_radius)
+                    })
+                }
+                SteeringBehavior::Wander {
+                    radius,
+                    distance,
+                    jitter,
+                } => steer_wander(
+                    velocity,
+                    &mut wander_angle,
+                    radius,
+                    distance,
+                    jitter,
+                    max_speed,
+                    rng,
+                ),
+                SteeringBehavior::AvoidWalls { look_ahead } => steer_avoid_walls(
+                    position,
+                    size,
+                    velocity,
+                    look_ahead,
+                    max_speed,
+                    wall_query.iter().map(|wall_hitbox| &wall_hitbox.rect),
+                ),
+                SteeringBehavior::Separation { radius } => {
+                    steer_separation(entity, position, &neighbors, radius, max_speed)
+                }
+                SteeringBehavior::Alignment { radius } => {
+                    steer_alignment(entity, position, &neighbors, radius, max_speed)
+                }
+                SteeringBehavior::Cohesion { radius } => {
+                    steer_cohesion(entity, position, &neighbors, radius, max_speed)
+                }
+            };
+            desired += force * *weight;
+        }
+        steering.wander_angle = wander_angle;
+
+        let desired = desired.clamp_length_max(max_speed);
+        let steer = (desired - velocity).clamp_length_max(steering.max_force * TIME_STEP);
+        let new_velocity = (velocity + steer).clamp_length_max(max_speed);
+
+        monster_mov.direction = new_velocity / monster_mov.speed;
+    }
+}
@@ -0,0 +1,373 @@
+use fstrings::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use bevy::asset::{AssetLoader, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::utils::BoxedFuture;
+
+use crate::test_bevy::*;
+
+// Maps made with the Tiled editor, exported as JSON (*.tmj or *.tiled.json). They go through the
+// same validation and spawning as the ASCII maps:
+//
+// - tile layers: every tile of an (embedded) tileset says what it is with a "kind" property
+//   (any TileKind name), or with the bool properties "stop", "reflect" and "destroy" that map
+//   onto the CollideCmp behaviors. Tiles without properties are empty floor, later layers
+//   override earlier ones.
+// - object layers: objects whose type (class in newer Tiled) is PlayerSpawn or MonsterSpawn mark
+//   the cell they sit in, Trigger and Exit objects become trigger zones of their own size.
+// - map properties: "name", "topleft_x" and "topleft_y".
+//
+// TMX (XML) files are not supported, export them as JSON from Tiled. Tile layers have to be
+// stored as CSV (no base64, no compression) and the map can't be infinite.
+#[derive(Debug, Deserialize)]
+pub struct TiledMap {
+    #[serde(default)]
+    pub infinite: bool,
+    pub width: usize,
+    pub height: usize,
+    pub tilewidth: f32,
+    pub tileheight: f32,
+    pub layers: Vec<TiledLayer>,
+    #[serde(default)]
+    pub tilesets: Vec<TiledTileset>,
+    #[serde(default)]
+    pub properties: Vec<TiledProperty>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+pub enum TiledLayer {
+    #[serde(rename = "tilelayer")]
+    Tiles {
+        #[serde(default)]
+        name: String,
+        // csv when left out
+        #[serde(default)]
+        encoding: Option<String>,
+        #[serde(default)]
+        compression: Option<String>,
+        // the gids for csv, a string for base64, missing in the chunked layers of infinite maps
+        #[serde(default)]
+        data: serde_json::Value,
+    },
+    #[serde(rename = "objectgroup")]
+    Objects {
+        #[serde(default)]
+        name: String,
+        objects: Vec<TiledObject>,
+    },
+    // image layers, groups...
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TiledTileset {
+    pub firstgid: u32,
+    // set when the tileset lives in its own file, which is not supported
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub tiles: Vec<TiledTile>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TiledTile {
+    pub id: u32,
+    #[serde(default)]
+    pub properties: Vec<TiledProperty>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TiledObject {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default, rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub class: String,
+    pub x: f32,
+    pub y: f32,
+    #[serde(default)]
+    pub width: f32,
+    #[serde(default)]
+    pub height: f32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TiledProperty {
+    pub name: String,
+    pub value: serde_json::Value,
+}
+
+// the top bits of a gid are the flip flags
+const TILED_GID_MASK: u32 = 0x1FFF_FFFF;
+
+fn find_property<'a>(properties: &'a [TiledProperty], name: &str) -> Option<&'a serde_json::Value> {
+    properties
+        .iter()
+        .find(|property| property.name == name)
+        .map(|property| &property.value)
+}
+
+fn tiled_tile_kind(properties: &[TiledProperty]) -> Result<TileKind, anyhow::Error> {
+    if let Some(kind) = find_property(properties, "kind").and_then(|value| value.as_str()) {
+        return TileKind::from_name(kind)
+            .ok_or_else(|| anyhow::anyhow!("unknown tile kind \"{}\"", kind));
+    }
+    let flag = |name| {
+        find_property(properties, name)
+            .and_then(|value| value.as_bool())
+            .unwrap_or(false)
+    };
+    Ok(match (flag("stop"), flag("reflect"), flag("destroy")) {
+        (_, _, true) => TileKind::DestructibleWall,
+        (true, true, _) => TileKind::Wall,
+        (true, false, _) => TileKind::StopWall,
+        (false, true, _) => TileKind::ReflectWall,
+        (false, false, _) => TileKind::Empty,
+    })
+}
+
+// The gids of a tile layer, row by row
+fn tiled_layer_gids(
+    name: &str,
+    encoding: &Option<String>,
+    compression: &Option<String>,
+    data: &serde_json::Value,
+) -> Result<Vec<u32>, anyhow::Error> {
+    let encoding = encoding.as_deref().unwrap_or("csv");
+    let compression = compression.as_deref().unwrap_or("");
+    if encoding != "csv" || !compression.is_empty() {
+        return Err(MapErrorKind::TiledEncoding {
+            layer: STR(name),
+            encoding: format!("{} {}", encoding, compression).trim().to_string(),
+        }
+        .into());
+    }
+    serde_json::from_value(data.clone())
+        .map_err(|err| anyhow::anyhow!("tile layer \"{}\" has no valid data: {}", name, err))
+}
+
+pub fn import_tiled_map(tiled: &TiledMap) -> Result<Map, anyhow::Error> {
+    if tiled.infinite {
+        return Err(MapErrorKind::TiledInfinite.into());
+    }
+    let legend = MapLegend::default();
+    let glyph_of = |kind: TileKind| {
+        legend
+            .0
+            .iter()
+            .find(|(_, legend_kind)| **legend_kind == kind)
+            .map(|(glyph, _)| *glyph)
+            .unwrap()
+    };
+
+    let mut tile_kinds = HashMap::new();
+    for tileset in tiled.tilesets.iter() {
+        if let Some(source) = &tileset.source {
+            anyhow::bail!(
+                "external tileset {} is not supported, embed it in the map",
+                source
+            );
+        }
+        for tile in tileset.tiles.iter() {
+            tile_kinds.insert(
+                tileset.firstgid + tile.id,
+                tiled_tile_kind(&tile.properties)?,
+            );
+        }
+    }
+
+    let block_size = Vec2::new(tiled.tilewidth, tiled.tileheight);
+    let mut grid = vec![vec![glyph_of(TileKind::Empty); tiled.width]; tiled.height];
+    let mut triggers = Vec::new();
+    for layer in tiled.layers.iter() {
+        match layer {
+            TiledLayer::Tiles {
+                name,
+                encoding,
+                compression,
+                data,
+            } => {
+                let data = tiled_layer_gids(name, encoding, compression, data)?;
+                if data.len() != tiled.width * tiled.height {
+                    anyhow::bail!(
+                        "tile layer \"{}\" has {} tiles, expected {}x{}",
+                        name,
+                        data.len(),
+                        tiled.width,
+                        tiled.height
+                    );
+                }
+                for (i, gid) in data.iter().enumerate() {
+                    let gid = gid & TILED_GID_MASK;
+                    if gid == 0 {
+                        continue;
+                    }
+                    let kind = tile_kinds.get(&gid).copied().unwrap_or(TileKind::Empty);
+                    if kind != TileKind::Empty {
+                        grid[i / tiled.width][i % tiled.width] = glyph_of(kind);
+                    }
+                }
+            }
+            TiledLayer::Objects { name, objects } => {
+                for object in objects.iter() {
+                    let kind = if object.kind.is_empty() {
+                        &object.class
+                    } else {
+                        &object.kind
+                    };
+                    match TileKind::from_name(kind) {
+                        Some(spawn @ TileKind::PlayerSpawn)
+                        | Some(spawn @ TileKind::MonsterSpawn) => {
+                            let col = (object.x / block_size.x) as usize;
+                            let row = (object.y / block_size.y) as usize;
+                            if col >= tiled.width || row >= tiled.height {
+                                anyhow::bail!(
+                                    "object \"{}\" of layer \"{}\" is outside the map",
+                                    object.name,
+                                    name
+                                );
+                            }
+                            grid[row][col] = glyph_of(spawn);
+                        }
+                        Some(TileKind::Exit) => triggers.push(MapTrigger {
+                            name: STR(EXIT_TRIGGER_NAME),
+                            offset: Vec2::new(object.x, object.y),
+                            size: Vec2::new(object.width, object.height),
+                        }),
+                        Some(TileKind::Trigger) => triggers.push(MapTrigger {
+                            name: object.name.clone(),
+                            offset: Vec2::new(object.x, object.y),
+                            size: Vec2::new(object.width, object.height),
+                        }),
+                        _ => {}
+                    }
+                }
+            }
+            TiledLayer::Other => {}
+        }
+    }
+
+    let name = find_property(&tiled.properties, "name")
+        .and_then(|value| value.as_str())
+        .unwrap_or("Unnamed");
+    let topleft_coord = |key| {
+        find_property(&tiled.properties, key)
+            .and_then(|value| value.as_f64())
+            .unwrap_or(0.) as f32
+    };
+    let rows: Vec<String> = grid.iter().map(|row| row.iter().collect()).collect();
+    let rows: Vec<&str> = rows.iter().map(|row| row.as_str()).collect();
+    // errors point at the grid row, counted from 1
+    let map = build_map(
+        STR(name),
+        block_size,
+        Vec2::new(topleft_coord("topleft_x"), topleft_coord("topleft_y")),
+        legend.clone(),
+        &rows,
+        1,
+        triggers,
+    )?;
+    Ok(map)
+}
+
+#[derive(Default)]
+pub struct TiledMapLoader;
+
+impl AssetLoader for TiledMapLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let tiled: TiledMap = serde_json::from_slice(bytes)?;
+            let map = import_tiled_map(&tiled)?;
+            load_context.set_default_asset(LoadedAsset::new(map));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["tmj", "tiled.json"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = include_str!("../../assets/maps/sample.tiled.json");
+
+    fn sample() -> serde_json::Value {
+        serde_json::from_str(SAMPLE).unwrap()
+    }
+
+    fn import(json: serde_json::Value) -> Result<Map, anyhow::Error> {
+        import_tiled_map(&serde_json::from_value(json).unwrap())
+    }
+
+    fn error_kind(json: serde_json::Value) -> MapErrorKind {
+        import(json)
+            .unwrap_err()
+            .downcast::<MapErrorKind>()
+            .unwrap()
+    }
+
+    #[test]
+    fn imports_the_sample() {
+        let map = import(sample()).unwrap();
+        assert_eq!(map.name(), "Tiled Sample");
+        assert_eq!(map.map_size(), (6, 6));
+        assert_eq!(map.topleft(), Vec2::new(40., 40.));
+        let grid = map.grid();
+        assert_eq!(grid[0], vec!['*'; 6]);
+        assert_eq!(grid[1][1], 'P');
+        assert_eq!(grid[2][3], '#');
+        assert_eq!(grid[4][4], 'M');
+        assert_eq!(map.triggers().len(), 1);
+        assert_eq!(map.triggers()[0].name, EXIT_TRIGGER_NAME);
+    }
+
+    #[test]
+    fn rejects_base64_layers() {
+        let mut json = sample();
+        json["layers"][0]["encoding"] = "base64".into();
+        json["layers"][0]["data"] = "AQAAAAEAAAA=".into();
+        assert_eq!(
+            error_kind(json),
+            MapErrorKind::TiledEncoding {
+                layer: STR("walls"),
+                encoding: STR("base64"),
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_compressed_layers() {
+        let mut json = sample();
+        json["layers"][0]["encoding"] = "base64".into();
+        json["layers"][0]["compression"] = "zlib".into();
+        json["layers"][0]["data"] = "eJxjZGBgYAAAAAwAAQ==".into();
+        assert_eq!(
+            error_kind(json),
+            MapErrorKind::TiledEncoding {
+                layer: STR("walls"),
+                encoding: STR("base64 zlib"),
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_infinite_maps() {
+        let mut json = sample();
+        json["infinite"] = true.into();
+        json["layers"][0].as_object_mut().unwrap().remove("data");
+        json["layers"][0]["chunks"] = serde_json::json!([]);
+        assert_eq!(error_kind(json), MapErrorKind::TiledInfinite);
+    }
+}
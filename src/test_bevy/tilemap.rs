@@ -0,0 +1,151 @@
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, Mesh};
+use bevy::render::pipeline::PrimitiveTopology;
+
+use crate::test_bevy::*;
+
+// The static walls are drawn from assets/textures/walls.png, a 4x4 atlas of 16x16 tiles. The
+// tile of a cell is picked from the cells around it (auto-tiling): its index is a bitmask of the
+// neighbors that are the same kind of wall, so the atlas only has to draw a border on the sides
+// that are open.
+pub const WALL_ATLAS_PATH: &str = "textures/walls.png";
+const WALL_ATLAS_COLUMNS: usize = 4;
+const WALL_ATLAS_ROWS: usize = 4;
+const WALL_ATLAS_TILE_PIXELS: f32 = 16.;
+
+const NEIGHBOR_NORTH: usize = 1;
+const NEIGHBOR_EAST: usize = 2;
+const NEIGHBOR_SOUTH: usize = 4;
+const NEIGHBOR_WEST: usize = 8;
+
+// Cells per side of a chunk. Every chunk is one mesh per wall kind, so a map is drawn with a
+// handful of entities however many walls it has.
+pub const MAP_CHUNK_SIZE: usize = 16;
+
+pub struct MapChunkCmp {
+    pub col: usize,
+    pub row: usize,
+}
+
+pub fn wall_autotile_index(grid: &[Vec<Option<TileKind>>], col: usize, row: usize) -> usize {
+    let kind = grid[row][col];
+    let same = |col: Option<usize>, row: Option<usize>| match (col, row) {
+        (Some(col), Some(row)) => grid.get(row).and_then(|r| r.get(col)).copied() == Some(kind),
+        _ => false,
+    };
+    let mut index = 0;
+    if same(Some(col), row.checked_sub(1)) {
+        index |= NEIGHBOR_NORTH;
+    }
+    if same(Some(col + 1), Some(row)) {
+        index |= NEIGHBOR_EAST;
+    }
+    if same(Some(col), Some(row + 1)) {
+        index |= NEIGHBOR_SOUTH;
+    }
+    if same(col.checked_sub(1), Some(row)) {
+        index |= NEIGHBOR_WEST;
+    }
+    index
+}
+
+// uv rect (min, max) of an atlas tile, pulled in by half a texel so neighbors don't bleed in
+fn wall_atlas_uv(index: usize) -> (Vec2, Vec2) {
+    let tile = Vec2::new(1. / WALL_ATLAS_COLUMNS as f32, 1. / WALL_ATLAS_ROWS as f32);
+    let half_texel = tile / WALL_ATLAS_TILE_PIXELS / 2.;
+    let min = Vec2::new(
+        (index % WALL_ATLAS_COLUMNS) as f32,
+        (index / WALL_ATLAS_COLUMNS) as f32,
+    ) * tile;
+    (min + half_texel, min + tile - half_texel)
+}
+
+// One quad per cell, in bevy coordinates relative to the chunk's top-left corner
+fn wall_chunk_mesh(cells: &[(Vec2, usize)], block_size: Vec2) -> Mesh {
+    let mut positions = Vec::with_capacity(cells.len() * 4);
+    let mut normals = Vec::with_capacity(cells.len() * 4);
+    let mut uvs = Vec::with_capacity(cells.len() * 4);
+    let mut indices = Vec::with_capacity(cells.len() * 6);
+    for (offset, atlas_index) in cells.iter() {
+        let (uv_min, uv_max) = wall_atlas_uv(*atlas_index);
+        let (left, top) = (offset.x, -offset.y);
+        let (right, bottom) = (left + block_size.x, top - block_size.y);
+        let first = positions.len() as u32;
+        positions.extend_from_slice(&[
+            [left, top, 0.],
+            [right, top, 0.],
+            [right, bottom, 0.],
+            [left, bottom, 0.],
+        ]);
+        normals.extend_from_slice(&[[0., 0., 1.]; 4]);
+        uvs.extend_from_slice(&[
+            [uv_min.x, uv_min.y],
+            [uv_max.x, uv_min.y],
+            [uv_max.x, uv_max.y],
+            [uv_min.x, uv_max.y],
+        ]);
+        // counter-clockwise, y goes up in bevy
+        indices.extend_from_slice(&[first, first + 3, first + 2, first, first + 2, first + 1]);
+    }
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
+
+// Spawns the static walls of the map as chunk meshes. They go through the sprite pipeline: its
+// vertex shader scales the mesh by the sprite size, so a 1x1 sprite draws the mesh as it is.
+pub fn spawn_wall_chunks(
+    commands: &mut Commands,
+    materials: &Res<Materials>,
+    meshes: &mut Assets<Mesh>,
+    map: &Map,
+) {
+    let grid = map.tile_grid();
+    let height = grid.len();
+    let width = grid.first().map_or(0, |row| row.len());
+    let block_size = map.block_size();
+
+    for chunk_row in (0..height).step_by(MAP_CHUNK_SIZE) {
+        for chunk_col in (0..width).step_by(MAP_CHUNK_SIZE) {
+            for kind in [TileKind::Wall, TileKind::StopWall, TileKind::ReflectWall].iter() {
+                let mut cells = Vec::new();
+                for row in chunk_row..(chunk_row + MAP_CHUNK_SIZE).min(height) {
+                    for col in chunk_col..(chunk_col + MAP_CHUNK_SIZE).min(width) {
+                        if grid[row][col] == Some(*kind) {
+                            let offset = Vec2::new(
+                                (col - chunk_col) as f32 * block_size.x,
+                                (row - chunk_row) as f32 * block_size.y,
+                            );
+                            cells.push((offset, wall_autotile_index(&grid, col, row)));
+                        }
+                    }
+                }
+                if cells.is_empty() {
+                    continue;
+                }
+
+                let bevy_sp = world_to_bevy(&Point::new(map.cell_topleft(chunk_col, chunk_row)));
+                commands
+                    .spawn_bundle(SpriteBundle {
+                        mesh: meshes.add(wall_chunk_mesh(&cells, block_size)),
+                        material: wall_material(materials, *kind).clone(),
+                        sprite: Sprite::new(Vec2::ONE),
+                        transform: Transform::from_xyz(bevy_sp.x(), bevy_sp.y(), 1.),
+                        ..Default::default()
+                    })
+                    .insert(MapTileCmp)
+                    .insert(MapChunkCmp {
+                        col: chunk_col,
+                        row: chunk_row,
+                    })
+                    .insert(NameCmp(format!(
+                        "{:?} chunk {} {}",
+                        kind, chunk_col, chunk_row
+                    )));
+            }
+        }
+    }
+}
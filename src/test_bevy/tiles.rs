@@ -0,0 +1,456 @@
+use fstrings::*;
+use std::collections::HashMap;
+
+use bevy::input::*;
+use bevy::prelude::*;
+use bevy::window::*;
+
+use crate::test_bevy::*;
+
+pub struct TilesPlugin;
+
+impl Plugin for TilesPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_event::<TriggerEnteredEvent>().add_system_set(
+            SystemSet::on_update(AppState::Playing)
+                .with_system(door_toggle.system().after("input_actions_system"))
+                .with_system(pickup_collect.system())
+                .with_system(trigger_enter.system()),
+        );
+    }
+}
+
+// What a glyph of the map grid turns into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileKind {
+    Empty,
+    // stops the player and reflects monsters
+    Wall,
+    StopWall,
+    ReflectWall,
+    DestructibleWall,
+    Door,
+    HealthPickup,
+    Trigger,
+    // trigger that takes the player to the next level
+    Exit,
+    PlayerSpawn,
+    MonsterSpawn,
+}
+
+impl TileKind {
+    pub fn from_name(name: &str) -> Option<TileKind> {
+        match name {
+            "Empty" => Some(TileKind::Empty),
+            "Wall" => Some(TileKind::Wall),
+            "StopWall" => Some(TileKind::StopWall),
+            "ReflectWall" => Some(TileKind::ReflectWall),
+            "DestructibleWall" => Some(TileKind::DestructibleWall),
+            "Door" => Some(TileKind::Door),
+            "HealthPickup" => Some(TileKind::HealthPickup),
+            "Trigger" => Some(TileKind::Trigger),
+            "Exit" => Some(TileKind::Exit),
+            "PlayerSpawn" => Some(TileKind::PlayerSpawn),
+            "MonsterSpawn" => Some(TileKind::MonsterSpawn),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TileKind::Empty => "Empty",
+            TileKind::Wall => "Wall",
+            TileKind::StopWall => "StopWall",
+            TileKind::ReflectWall => "ReflectWall",
+            TileKind::DestructibleWall => "DestructibleWall",
+            TileKind::Door => "Door",
+            TileKind::HealthPickup => "HealthPickup",
+            TileKind::Trigger => "Trigger",
+            TileKind::Exit => "Exit",
+            TileKind::PlayerSpawn => "PlayerSpawn",
+            TileKind::MonsterSpawn => "MonsterSpawn",
+        }
+    }
+
+    // walls without any state of their own, their colliders get merged by the map
+    pub fn is_static_wall(self) -> bool {
+        matches!(
+            self,
+            TileKind::Wall | TileKind::StopWall | TileKind::ReflectWall
+        )
+    }
+}
+
+// Glyph -> tile kind. Maps start from the default legend and can override glyphs in their header
+// with lines like "legend: & = ReflectWall".
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapLegend(pub HashMap<char, TileKind>);
+
+impl Default for MapLegend {
+    fn default() -> Self {
+        Self(
+            [
+                (' ', TileKind::Empty),
+                ('*', TileKind::Wall),
+                ('#', TileKind::StopWall),
+                ('%', TileKind::ReflectWall),
+                ('+', TileKind::DestructibleWall),
+                ('D', TileKind::Door),
+                ('H', TileKind::HealthPickup),
+                ('T', TileKind::Trigger),
+                ('X', TileKind::Exit),
+                ('P', TileKind::PlayerSpawn),
+                ('M', TileKind::MonsterSpawn),
+            ]
+            .iter()
+            .cloned()
+            .collect(),
+        )
+    }
+}
+
+impl MapLegend {
+    pub fn get(&self, glyph: char) -> Option<TileKind> {
+        self.0.get(&glyph).copied()
+    }
+}
+
+// Everything spawned from the map grid, so a level can be torn down in one go
+pub struct MapTileCmp;
+
+pub struct DoorCmp {
+    open: bool,
+}
+
+impl DoorCmp {
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+}
+
+// An open door lets everything through and isn't drawn
+pub fn set_door_open(
+    door: &mut DoorCmp,
+    door_collide: &mut CollideCmp,
+    door_visible: &mut Visible,
+    open: bool,
+) {
+    door.open = open;
+    door_collide.property = if open {
+        CollideType::None as u32
+    } else {
+        CollideType::Stop as u32 | CollideType::Reflect as u32
+    };
+    door_visible.is_visible = !open;
+}
+
+pub enum PickupKind {
+    Health(f32),
+}
+
+pub struct PickupCmp {
+    kind: PickupKind,
+}
+
+pub struct TriggerCmp {
+    pub name: String,
+    // the player is inside, so the event only fires when entering
+    occupied: bool,
+}
+
+pub struct TriggerEnteredEvent {
+    pub trigger: Entity,
+    pub name: String,
+}
+
+#[derive(Bundle)]
+pub struct WallBundle {
+    wall: WallEntity,
+    tile: MapTileCmp,
+    name: NameCmp,
+    hitbox: RectangleHitboxCmp,
+    collide: CollideCmp,
+    #[bundle]
+    sprite: SpriteBundle,
+}
+
+#[derive(Bundle)]
+pub struct PickupBundle {
+    pickup: PickupCmp,
+    tile: MapTileCmp,
+    name: NameCmp,
+    hitbox: RectangleHitboxCmp,
+    #[bundle]
+    sprite: SpriteBundle,
+}
+
+#[derive(Bundle)]
+pub struct TriggerBundle {
+    trigger: TriggerCmp,
+    tile: MapTileCmp,
+    name: NameCmp,
+    hitbox: RectangleHitboxCmp,
+    #[bundle]
+    sprite: SpriteBundle,
+}
+
+pub fn tile_sprite(
+    material: &Handle<ColorMaterial>,
+    sp: &Point,
+    size: Vec2,
+    z: f32,
+) -> SpriteBundle {
+    let mut bevy_sp = world_to_bevy(sp);
+    bevy_sp = bevy_sp + Point::new((size.x / 2., -size.y / 2.));
+    SpriteBundle {
+        material: material.clone(),
+        sprite: Sprite::new(size),
+        transform: Transform::from_xyz(bevy_sp.x(), bevy_sp.y(), z),
+        ..Default::default()
+    }
+}
+
+fn wall_bundle(
+    material: &Handle<ColorMaterial>,
+    sp: Point,
+    size: Vec2,
+    property: u32,
+) -> WallBundle {
+    WallBundle {
+        wall: WallEntity,
+        tile: MapTileCmp,
+        name: NameCmp(f!("Wall {sp}")),
+        hitbox: RectangleHitboxCmp {
+            rect: collision::Rect::new((sp, size.x, size.y)),
+        },
+        collide: CollideCmp { property },
+        sprite: tile_sprite(material, &sp, size, 1.),
+    }
+}
+
+pub fn wall_material(materials: &Materials, kind: TileKind) -> &Handle<ColorMaterial> {
+    match kind {
+        TileKind::StopWall => &materials.stop_wall_materials,
+        TileKind::ReflectWall => &materials.reflect_wall_materials,
+        _ => &materials.wall_materials,
+    }
+}
+
+fn wall_property(kind: TileKind) -> u32 {
+    match kind {
+        TileKind::StopWall => CollideType::Stop as u32,
+        TileKind::ReflectWall => CollideType::Reflect as u32,
+        _ => CollideType::Stop as u32 | CollideType::Reflect as u32,
+    }
+}
+
+// Invisible hitbox covering a block of static wall tiles, see Map::wall_rects. It keeps the
+// material of its tiles so the collision highlight still shows.
+pub fn spawn_wall_collider(
+    commands: &mut Commands,
+    materials: &Res<Materials>,
+    kind: TileKind,
+    sp: Vec2,
+    size: Vec2,
+) {
+    let sp = Point::new(sp);
+    commands
+        .spawn()
+        .insert(WallEntity)
+        .insert(MapTileCmp)
+        .insert(NameCmp(f!("Wall collider {sp}")))
+        .insert(RectangleHitboxCmp {
+            rect: collision::Rect::new((sp, size.x, size.y)),
+        })
+        .insert(CollideCmp {
+            property: wall_property(kind),
+        })
+        .insert(wall_material(materials, kind).clone());
+}
+
+// Spawns the entity of one grid cell. sp is the top-left corner of the cell.
+pub fn spawn_tile(
+    commands: &mut Commands,
+    materials: &Res<Materials>,
+    kind: TileKind,
+    sp: Vec2,
+    block_size: Vec2,
+) {
+    let sp = Point::new(sp);
+    match kind {
+        TileKind::DestructibleWall => {
+            commands
+                .spawn_bundle(wall_bundle(
+                    &materials.destructible_wall_materials,
+                    sp,
+                    block_size,
+                    CollideType::Stop as u32
+                        | CollideType::Reflect as u32
+                        | CollideType::Destroy as u32,
+                ))
+                .insert(HealthCmp::new(50., 0.));
+        }
+        TileKind::Door => {
+            commands
+                .spawn_bundle(wall_bundle(
+                    &materials.door_materials,
+                    sp,
+                    block_size,
+                    CollideType::Stop as u32 | CollideType::Reflect as u32,
+                ))
+                .insert(DoorCmp { open: false });
+        }
+        TileKind::HealthPickup => {
+            let size = block_size / 2.;
+            let sp = sp + (block_size - size) / 2.;
+            commands.spawn_bundle(PickupBundle {
+                pickup: PickupCmp {
+                    kind: PickupKind::Health(25.),
+                },
+                tile: MapTileCmp,
+                name: NameCmp(f!("Health pickup {sp}")),
+                hitbox: RectangleHitboxCmp {
+                    rect: collision::Rect::new((sp, size.x, size.y)),
+                },
+                sprite: tile_sprite(&materials.pickup_materials, &sp, size, 0.5),
+            });
+        }
+        TileKind::Trigger => {
+            spawn_trigger(commands, materials, f!("trigger {sp}"), sp.0, block_size);
+        }
+        TileKind::Exit => {
+            spawn_trigger(
+                commands,
+                materials,
+                STR(EXIT_TRIGGER_NAME),
+                sp.0,
+                block_size,
+            );
+        }
+        // the static walls are drawn and collide per chunk, the spawn points are picked up by
+        // spawn_map, none of them has an entity of its own
+        TileKind::Empty
+        | TileKind::Wall
+        | TileKind::StopWall
+        | TileKind::ReflectWall
+        | TileKind::PlayerSpawn
+        | TileKind::MonsterSpawn => {}
+    }
+}
+
+// Name of the triggers that end the level, also used for Tiled trigger objects
+pub const EXIT_TRIGGER_NAME: &str = "exit";
+
+pub fn spawn_trigger(
+    commands: &mut Commands,
+    materials: &Res<Materials>,
+    name: String,
+    sp: Vec2,
+    size: Vec2,
+) {
+    let sp = Point::new(sp);
+    let material = if name == EXIT_TRIGGER_NAME {
+        &materials.exit_materials
+    } else {
+        &materials.trigger_materials
+    };
+    commands.spawn_bundle(TriggerBundle {
+        trigger: TriggerCmp {
+            name: name.clone(),
+            occupied: false,
+        },
+        tile: MapTileCmp,
+        name: NameCmp(f!("Trigger {name}")),
+        hitbox: RectangleHitboxCmp {
+            rect: collision::Rect::new((sp, size.x, size.y)),
+        },
+        sprite: tile_sprite(material, &sp, size, 0.1),
+    });
+}
+
+const DOOR_REACH: f32 = 40.;
+
+// Interact (E) opens / closes the doors next to the player
+fn door_toggle(
+    action_state: Res<ActionState>,
+    player_query: Query<(&PlayerIdCmp, &RectangleHitboxCmp), With<PlayerEntity>>,
+    mut door_query: Query<(
+        &mut DoorCmp,
+        &mut CollideCmp,
+        &mut Visible,
+        &RectangleHitboxCmp,
+    )>,
+) {
+    let interacting: Vec<Point> = player_query
+        .iter()
+        .filter(|(player_id, _)| {
+            action_state
+                .player(player_id.0)
+                .is_some_and(|actions| actions.just_pressed(InputAction::Interact))
+        })
+        .map(|(_, player_hitbox)| player_hitbox.rect.center())
+        .collect();
+    if interacting.is_empty() {
+        return;
+    }
+    for (mut door, mut door_collide, mut door_visible, door_hitbox) in door_query.iter_mut() {
+        // a door two players reach for at once only flips once
+        let door_center = door_hitbox.rect.center();
+        if !interacting
+            .iter()
+            .any(|player_center| door_center.distance(player_center) <= DOOR_REACH)
+        {
+            continue;
+        }
+        let open = !door.open;
+        set_door_open(&mut door, &mut door_collide, &mut door_visible, open);
+    }
+}
+
+fn pickup_collect(
+    mut commands: Commands,
+    mut player_query: Query<(&RectangleHitboxCmp, Option<&mut HealthCmp>), With<PlayerEntity>>,
+    pickup_query: Query<(Entity, &PickupCmp, &RectangleHitboxCmp)>,
+) {
+    let mut collected = Vec::new();
+    for (player_hitbox, mut player_health) in player_query.iter_mut() {
+        for (pickup, pickup_cmp, pickup_hitbox) in pickup_query.iter() {
+            if collected.contains(&pickup) {
+                continue;
+            }
+            if !pickup_hitbox.rect.is_rect_overlap(&player_hitbox.rect) {
+                continue;
+            }
+            match pickup_cmp.kind {
+                PickupKind::Health(amount) => {
+                    if let Some(health) = player_health.as_deref_mut() {
+                        health.heal(amount);
+                    }
+                }
+            }
+            commands.entity(pickup).despawn();
+            collected.push(pickup);
+        }
+    }
+}
+
+fn trigger_enter(
+    player_query: Query<&RectangleHitboxCmp, With<PlayerEntity>>,
+    mut trigger_query: Query<(Entity, &mut TriggerCmp, &RectangleHitboxCmp)>,
+    mut trigger_events: EventWriter<TriggerEnteredEvent>,
+) {
+    for (trigger, mut trigger_cmp, trigger_hitbox) in trigger_query.iter_mut() {
+        // occupied while any of the players is in it
+        let inside = player_query
+            .iter()
+            .any(|player_hitbox| trigger_hitbox.rect.is_rect_overlap(&player_hitbox.rect));
+        if inside && !trigger_cmp.occupied {
+            println!("Player entered {}", trigger_cmp.name);
+            trigger_events.send(TriggerEnteredEvent {
+                trigger,
+                name: trigger_cmp.name.clone(),
+            });
+        }
+        trigger_cmp.occupied = inside;
+    }
+}